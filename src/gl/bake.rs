@@ -0,0 +1,199 @@
+use gl::*;
+
+/// Bakes the ambient occlusion of a `Model` into a texture laid out like its
+/// other maps, in the space of `Model::uv`.
+///
+/// The model is seen from `directions` points spread evenly on a sphere
+/// around it. For each one a depth pass like the one of `DepthShader`
+/// renders it into a shadow buffer, then a pass over the texture checks every
+/// texel against that buffer. A texel ends up with the cosine weighted
+/// fraction of the directions above its surface from which it is visible, so
+/// that an open surface stays white whatever its orientation.
+#[derive(Debug, Clone, Copy)]
+pub struct OcclusionBaker {
+    pub directions: usize,
+    /// side of the baked texture, in texels
+    pub size: i32,
+    /// side of the shadow buffers, in pixels
+    pub shadow_size: i32,
+    /// offset of the depth passes, against self shadowing
+    pub bias: DepthBias,
+    /// texels the result is grown by around the UV islands, so that sampling
+    /// near their edges does not pick up the background
+    pub padding: i32,
+    /// worker threads of the passes
+    pub threads: usize,
+}
+
+/// Depth range of the shadow passes.
+const BAKE_DEPTH: f32 = 2000.0;
+
+impl OcclusionBaker {
+    pub fn new() -> OcclusionBaker {
+        OcclusionBaker {
+            directions: 128,
+            size: 1024,
+            shadow_size: 1024,
+            bias: DepthBias::with(-10.0, -2.0),
+            padding: 2,
+            threads: 1,
+        }
+    }
+
+    /// The grayscale occlusion map of `model`, white where nothing occludes
+    /// the surface. Like the textures `Model` loads, its first row is at
+    /// `v = 0`: flip it vertically before writing it out.
+    pub fn bake(&self, model: &Model) -> TGAImage {
+        let (center, radius) = bounds(model);
+        let n = (self.size * self.size) as usize;
+        let (mut visible, mut total) = (vec![0f32; n], vec![0f32; n]);
+        let mut covered = vec![false; n];
+
+        let mut light = Pipeline::new(self.shadow_size, self.shadow_size, BAKE_DEPTH);
+        light.threads = self.threads;
+        light.depth_bias = self.bias;
+        let mut texture = Pipeline::new(self.size, self.size, BAKE_DEPTH);
+        texture.threads = self.threads;
+        texture.depth = DepthState::new(BAKE_DEPTH).read_only();
+        texture.depth.func = CompareFunc::Always;
+        let mut texels = Framebuffer::new(self.size, self.size).with_color(Format::Rgba32F);
+
+        for i in 0..self.directions {
+            let dir = sphere_point(i, self.directions);
+            let mut camera = Camera::new(BAKE_DEPTH);
+            let up = if dir.y.abs() > 0.99 { Vec3f::new(1.0, 0.0, 0.0) } else { Vec3f::new(0.0, 1.0, 0.0) };
+            camera.lookat(dir, Vec3f::zero(), up);
+            // fit the bounding sphere of the model in the shadow buffer
+            let mut fit = Mat4::identity();
+            for j in 0..3 {
+                fit[(j, j)] = 1.0 / radius;
+                fit[(j, 3)] = -center[j] / radius;
+            }
+            camera.modelview = camera.modelview * fit;
+            camera.viewport(0, 0, self.shadow_size, self.shadow_size);
+            camera.projection(0.0);
+
+            let mut shadow = Framebuffer::new(self.shadow_size, self.shadow_size);
+            light.draw(&camera, model, &DepthShader::new(BAKE_DEPTH), &mut shadow);
+
+            let shader = BakeShader {
+                shadow: shadow.depth(),
+                shadow_size: self.shadow_size,
+                to_shadow: camera.viewport * camera.projection * camera.modelview,
+                dir: dir,
+                size: self.size as f32,
+            };
+            texels.clear_color([0.0; 4]);
+            texture.draw(&camera, model, &shader, &mut texels);
+            let values = texels.color(0).floats().unwrap();
+            for t in 0..n {
+                visible[t] += values[t * 4];
+                total[t] += values[t * 4 + 1];
+                covered[t] |= values[t * 4 + 3] > 0.0;
+            }
+        }
+
+        let mut ao: Vec<Option<f32>> = (0..n).map(|t| {
+            if !covered[t] { None } else if total[t] > 0.0 { Some(visible[t] / total[t]) } else { Some(1.0) }
+        }).collect();
+        for _ in 0..self.padding {
+            ao = dilate(&ao, self.size);
+        }
+        let mut image = TGAImage::with_info(self.size as isize, self.size as isize, tga_image::GRAYSCALE);
+        for (dst, v) in image.buffer().iter_mut().zip(ao.iter()) {
+            *dst = (v.unwrap_or(1.0) * 255.0 + 0.5) as u8;
+        }
+        image
+    }
+}
+
+impl Default for OcclusionBaker {
+    fn default() -> OcclusionBaker {
+        OcclusionBaker::new()
+    }
+}
+
+/// Draws the triangles of a model at their place in its texture and writes,
+/// for one direction, `[visible * cos, cos, 0, 1]` where `cos` is the cosine
+/// between the direction and the surface, 0 when facing away.
+#[derive(Clone)]
+struct BakeShader<'a> {
+    shadow: &'a [f32],
+    shadow_size: i32,
+    to_shadow: Mat4,
+    dir: Vec3f,
+    size: f32,
+}
+
+/// Varyings: the model space position and normal.
+impl<'a> Shader for BakeShader<'a> {
+    type Varyings = (Vec3f, Vec3f);
+    fn vertex(&self, _camera: &Camera, model: &Model, iface: i32, nthvert: i32) -> (Vec4f, (Vec3f, Vec3f)) {
+        let uv = model.uv(iface as usize, nthvert as usize);
+        (Vec4f::new([uv[0] * self.size, uv[1] * self.size, 0.0, 1.0]), (model.face_vert(iface, nthvert), model.face_normal(iface, nthvert)))
+    }
+    fn fragment(&self, _v: &(Vec3f, Vec3f), color: &mut Color) -> bool {
+        *color = Color::with_color(RGBAColor(255, 255, 255, 255));
+        false
+    }
+    fn fragment_outputs(&self, &(pos, n): &(Vec3f, Vec3f), out: &mut FragmentOutputs) -> bool {
+        let cos = (n.normalize() * self.dir).max(0.0);
+        let p = self.to_shadow * pos.embed(1.0);
+        let (x, y) = (p[0] as i32, p[1] as i32);
+        let lit = x >= 0 && y >= 0 && x < self.shadow_size && y < self.shadow_size
+            && p[2] >= self.shadow[(x + y * self.shadow_size) as usize];
+        out.set(0, [if lit { cos } else { 0.0 }, cos, 0.0, 1.0]);
+        false
+    }
+    fn interpolation(&self) -> Interpolation { Interpolation::NoPerspective }
+}
+
+/// Centre and radius of a sphere around the vertices of `model`.
+fn bounds(model: &Model) -> (Vec3f, f32) {
+    let (mut lo, mut hi) = (Vec3f::new(std::f32::MAX, std::f32::MAX, std::f32::MAX), Vec3f::new(std::f32::MIN, std::f32::MIN, std::f32::MIN));
+    for i in 0..model.nverts() {
+        let v = model.vert(i);
+        for j in 0..3 {
+            lo[j] = lo[j].min(v[j]);
+            hi[j] = hi[j].max(v[j]);
+        }
+    }
+    let center = (lo + hi) * 0.5;
+    let radius = (0..model.nverts()).map(|i| (model.vert(i) - center).norm() as f32).fold(0.0, f32::max);
+    // a little room so that nothing touches the edges of the buffer
+    (center, radius.max(1.0e-6) * 1.01)
+}
+
+/// The point `i` of a Fibonacci lattice of `count` points on the unit sphere.
+fn sphere_point(i: usize, count: usize) -> Vec3f {
+    let y = 1.0 - 2.0 * (i as f32 + 0.5) / count as f32;
+    let r = (1.0 - y * y).sqrt();
+    let phi = i as f32 * 2.399963;
+    Vec3f::new(r * phi.cos(), y, r * phi.sin())
+}
+
+/// Fill the empty texels next to covered ones with the mean of those.
+fn dilate(ao: &[Option<f32>], size: i32) -> Vec<Option<f32>> {
+    let mut ret = ao.to_vec();
+    for y in 0..size {
+        for x in 0..size {
+            if ao[(x + y * size) as usize].is_some() {
+                continue;
+            }
+            let (mut sum, mut count) = (0.0, 0);
+            for &(dx, dy) in [(-1, 0), (1, 0), (0, -1), (0, 1)].iter() {
+                let (i, j) = (x + dx, y + dy);
+                if i >= 0 && j >= 0 && i < size && j < size {
+                    if let Some(v) = ao[(i + j * size) as usize] {
+                        sum += v;
+                        count += 1;
+                    }
+                }
+            }
+            if count > 0 {
+                ret[(x + y * size) as usize] = Some(sum / count as f32);
+            }
+        }
+    }
+    ret
+}
@@ -0,0 +1,122 @@
+/// Weight applied to the source (shader output) or destination (stored
+/// pixel) colour before they are combined.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum BlendFactor {
+    Zero,
+    One,
+    SrcColor,
+    OneMinusSrcColor,
+    DstColor,
+    OneMinusDstColor,
+    SrcAlpha,
+    OneMinusSrcAlpha,
+    DstAlpha,
+    OneMinusDstAlpha,
+}
+
+/// How the weighted source and destination are combined. `Min` and `Max`
+/// ignore the factors.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum BlendEquation {
+    /// src + dst
+    Add,
+    /// src - dst
+    Subtract,
+    /// dst - src
+    ReverseSubtract,
+    Min,
+    Max,
+}
+
+/// Blend configuration of a `Pipeline`. Colours are in the byte order of the
+/// images, blue, green, red then alpha, normalized to `0..1`. A source without
+/// an alpha channel is opaque, so is a destination.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct BlendState {
+    pub enabled: bool,
+    pub src: BlendFactor,
+    pub dst: BlendFactor,
+    pub equation: BlendEquation,
+    /// factors for the alpha channel
+    pub src_alpha: BlendFactor,
+    pub dst_alpha: BlendFactor,
+}
+
+impl BlendState {
+    /// Blending disabled: the source replaces the destination.
+    pub fn new() -> BlendState {
+        BlendState {
+            enabled: false,
+            src: BlendFactor::One,
+            dst: BlendFactor::Zero,
+            equation: BlendEquation::Add,
+            src_alpha: BlendFactor::One,
+            dst_alpha: BlendFactor::Zero,
+        }
+    }
+    /// `src` and `dst` for all four channels.
+    #[allow(dead_code)]
+    pub fn with(src: BlendFactor, dst: BlendFactor, equation: BlendEquation) -> BlendState {
+        BlendState {
+            enabled: true,
+            src: src,
+            dst: dst,
+            equation: equation,
+            src_alpha: src,
+            dst_alpha: dst,
+        }
+    }
+    /// Usual transparency: `src * a + dst * (1 - a)`.
+    #[allow(dead_code)]
+    pub fn alpha() -> BlendState {
+        BlendState::with(BlendFactor::SrcAlpha, BlendFactor::OneMinusSrcAlpha, BlendEquation::Add)
+    }
+
+    fn factor(factor: BlendFactor, src: &[f32; 4], dst: &[f32; 4], channel: usize) -> f32 {
+        match factor {
+            BlendFactor::Zero => 0.0,
+            BlendFactor::One => 1.0,
+            BlendFactor::SrcColor => src[channel],
+            BlendFactor::OneMinusSrcColor => 1.0 - src[channel],
+            BlendFactor::DstColor => dst[channel],
+            BlendFactor::OneMinusDstColor => 1.0 - dst[channel],
+            BlendFactor::SrcAlpha => src[3],
+            BlendFactor::OneMinusSrcAlpha => 1.0 - src[3],
+            BlendFactor::DstAlpha => dst[3],
+            BlendFactor::OneMinusDstAlpha => 1.0 - dst[3],
+        }
+    }
+
+    /// Combine `src` with `dst`, the result is clamped to `0..1`.
+    pub fn apply(&self, src: [f32; 4], dst: [f32; 4]) -> [f32; 4] {
+        let mut ret = self.combine(src, dst);
+        for c in 0..4 {
+            ret[c] = ret[c].max(0.0).min(1.0);
+        }
+        ret
+    }
+    /// Same as `apply` without the clamping, for float targets.
+    pub fn combine(&self, src: [f32; 4], dst: [f32; 4]) -> [f32; 4] {
+        let mut ret = [0f32; 4];
+        for c in 0..4 {
+            let (fs, fd) = if c == 3 { (self.src_alpha, self.dst_alpha) } else { (self.src, self.dst) };
+            let s = src[c] * BlendState::factor(fs, &src, &dst, c);
+            let d = dst[c] * BlendState::factor(fd, &src, &dst, c);
+            let v = match self.equation {
+                BlendEquation::Add => s + d,
+                BlendEquation::Subtract => s - d,
+                BlendEquation::ReverseSubtract => d - s,
+                BlendEquation::Min => src[c].min(dst[c]),
+                BlendEquation::Max => src[c].max(dst[c]),
+            };
+            ret[c] = v;
+        }
+        ret
+    }
+}
+
+impl Default for BlendState {
+    fn default() -> BlendState {
+        BlendState::new()
+    }
+}
@@ -0,0 +1,136 @@
+use gl::geometry::*;
+
+/// A vertex produced by the clipper. `bar` holds the barycentric weights of
+/// the vertex relative to the unclipped triangle, so the varyings of a new
+/// vertex are the same weighted sum of the original three.
+#[derive(Debug, Clone, Copy)]
+pub struct ClipVertex {
+    pub pos: Vec4f,
+    pub bar: Vec3f,
+}
+
+impl ClipVertex {
+    fn lerp(&self, other: &ClipVertex, t: f32) -> ClipVertex {
+        ClipVertex {
+            pos: self.pos + (other.pos - self.pos) * t,
+            bar: self.bar + (other.bar - self.bar) * t,
+        }
+    }
+}
+
+/// Smallest `w` left by the near plane. `w` is the distance to the eye over
+/// the distance from the eye to the centre of the view, so the plane sits
+/// just in front of the eye.
+pub const NEAR_W: f32 = 0.01;
+
+/// The clip planes of the render volume.
+///
+/// Shaders hand back positions with the viewport already applied but before
+/// the divide by `w`, so the volume is `0 <= x <= width * w`,
+/// `0 <= y <= height * w` and `w >= NEAR_W`. The projection leaves z alone,
+/// so there are no z planes: the depth range does not bound anything in
+/// front of the eye, and the near plane takes care of geometry behind it.
+///
+/// The side planes can be pushed out by a guard band. The rasterizer only
+/// visits the pixels of the render target anyway, so a triangle that
+/// overhangs the screen by less than the band goes through untouched instead
+/// of being cut into pieces.
+#[derive(Debug, Clone, Copy)]
+pub struct Frustum {
+    /// `(n, d)` for every plane, `n * p >= d` inside
+    planes: [(Vec4f, f32); 5],
+}
+
+impl Frustum {
+    pub fn new(width: i32, height: i32) -> Frustum {
+        Frustum::with_guard_band(width, height, 0.0)
+    }
+    /// Side planes `guard` pixels outside the screen.
+    pub fn with_guard_band(width: i32, height: i32, guard: f32) -> Frustum {
+        Frustum {
+            planes: [
+                (Vec4f::new([1.0, 0.0, 0.0, guard]), 0.0),
+                (Vec4f::new([-1.0, 0.0, 0.0, width as f32 + guard]), 0.0),
+                (Vec4f::new([0.0, 1.0, 0.0, guard]), 0.0),
+                (Vec4f::new([0.0, -1.0, 0.0, height as f32 + guard]), 0.0),
+                (Vec4f::new([0.0, 0.0, 0.0, 1.0]), NEAR_W),
+            ],
+        }
+    }
+    /// Signed distance of `p` to every plane; negative means outside.
+    fn distances(&self, p: Vec4f) -> [f32; 5] {
+        let mut ret = [0f32; 5];
+        for i in 0..5 {
+            ret[i] = distance(&self.planes[i], p);
+        }
+        ret
+    }
+    pub fn contains(&self, p: Vec4f) -> bool {
+        self.distances(p).iter().all(|&d| d >= 0.0)
+    }
+    /// Clip a convex polygon against all the planes (Sutherland–Hodgman).
+    pub fn clip_polygon(&self, mut poly: Vec<ClipVertex>) -> Vec<ClipVertex> {
+        for plane in self.planes.iter() {
+            if poly.is_empty() {
+                break;
+            }
+            let mut out = Vec::with_capacity(poly.len() + 1);
+            for i in 0..poly.len() {
+                let cur = poly[i];
+                let next = poly[(i + 1) % poly.len()];
+                let dc = distance(plane, cur.pos);
+                let dn = distance(plane, next.pos);
+                if dc >= 0.0 {
+                    out.push(cur);
+                }
+                // always step from the inside vertex so that triangles sharing
+                // the edge get the exact same new vertex
+                if dc >= 0.0 && dn < 0.0 {
+                    out.push(cur.lerp(&next, dc / (dc - dn)));
+                } else if dc < 0.0 && dn >= 0.0 {
+                    out.push(next.lerp(&cur, dn / (dn - dc)));
+                }
+            }
+            poly = out;
+        }
+        poly
+    }
+    /// Clip the triangle `pts` and split whatever is left back into
+    /// triangles. A triangle that is entirely inside comes back untouched.
+    pub fn clip_triangle(&self, pts: &[Vec4f]) -> Vec<[ClipVertex; 3]> {
+        let verts = [
+            ClipVertex { pos: pts[0], bar: Vec3f::new(1, 0, 0) },
+            ClipVertex { pos: pts[1], bar: Vec3f::new(0, 1, 0) },
+            ClipVertex { pos: pts[2], bar: Vec3f::new(0, 0, 1) },
+        ];
+        if verts.iter().all(|v| self.contains(v.pos)) {
+            return vec![verts];
+        }
+        let poly = self.clip_polygon(verts.to_vec());
+        let mut ret = vec![];
+        for i in 1..poly.len().saturating_sub(1) {
+            ret.push([poly[0], poly[i], poly[i + 1]]);
+        }
+        ret
+    }
+    /// Clip the segment `a` - `b`, `None` when nothing is left of it.
+    pub fn clip_segment(&self, mut a: ClipVertex, mut b: ClipVertex) -> Option<(ClipVertex, ClipVertex)> {
+        for plane in self.planes.iter() {
+            let da = distance(plane, a.pos);
+            let db = distance(plane, b.pos);
+            if da < 0.0 && db < 0.0 {
+                return None;
+            }
+            if da < 0.0 {
+                a = b.lerp(&a, db / (db - da));
+            } else if db < 0.0 {
+                b = a.lerp(&b, da / (da - db));
+            }
+        }
+        Some((a, b))
+    }
+}
+
+fn distance(plane: &(Vec4f, f32), p: Vec4f) -> f32 {
+    (plane.0 * p) as f32 - plane.1
+}
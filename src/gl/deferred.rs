@@ -0,0 +1,158 @@
+use gl::*;
+
+/// A `Framebuffer` laid out for deferred shading: the geometry pass writes
+/// the surface attributes of the closest fragments, the lighting pass reads
+/// them back once per pixel with `Pipeline::full_screen`.
+#[derive(Debug, Clone)]
+pub struct GBuffer {
+    framebuffer: Framebuffer,
+}
+
+impl GBuffer {
+    /// `Rgba8` diffuse colour
+    pub const ALBEDO: usize = 0;
+    /// `Rgba32F` unit normal, `[x, y, z, 0]`
+    pub const NORMAL: usize = 1;
+    /// `R32F` specular exponent
+    pub const SPECULAR: usize = 2;
+    /// `Rgba32F` position, `[x, y, z, 1]`, `w` is 0 where nothing was drawn
+    pub const POSITION: usize = 3;
+
+    pub fn new(width: i32, height: i32) -> GBuffer {
+        GBuffer {
+            framebuffer: Framebuffer::new(width, height)
+                .with_color(Format::Rgba8)
+                .with_color(Format::Rgba32F)
+                .with_color(Format::R32F)
+                .with_color(Format::Rgba32F),
+        }
+    }
+    #[allow(dead_code)]
+    pub fn get_width(&self) -> i32 { self.framebuffer.get_width() }
+    #[allow(dead_code)]
+    pub fn get_height(&self) -> i32 { self.framebuffer.get_height() }
+    #[allow(dead_code)]
+    pub fn framebuffer(&self) -> &Framebuffer { &self.framebuffer }
+    /// For the geometry pass to draw into.
+    #[allow(dead_code)]
+    pub fn framebuffer_mut(&mut self) -> &mut Framebuffer { &mut self.framebuffer }
+
+    /// Whether a fragment was stored at `x`, `y`.
+    pub fn covered(&self, x: i32, y: i32) -> bool {
+        self.framebuffer.color(GBuffer::POSITION).get(x, y)[3] > 0.0
+    }
+    pub fn albedo(&self, x: i32, y: i32) -> [f32; 4] {
+        self.framebuffer.color(GBuffer::ALBEDO).get(x, y)
+    }
+    pub fn normal(&self, x: i32, y: i32) -> Vec3f {
+        let n = self.framebuffer.color(GBuffer::NORMAL).get(x, y);
+        Vec3f::new(n[0], n[1], n[2])
+    }
+    pub fn specular(&self, x: i32, y: i32) -> f32 {
+        self.framebuffer.color(GBuffer::SPECULAR).get(x, y)[0]
+    }
+    pub fn position(&self, x: i32, y: i32) -> Vec3f {
+        let p = self.framebuffer.color(GBuffer::POSITION).get(x, y);
+        Vec3f::new(p[0], p[1], p[2])
+    }
+}
+
+/// Geometry pass of deferred shading: stores the texture colour, the normal
+/// map and the specular map of `model` with the model space position into
+/// a `GBuffer`.
+#[derive(Clone)]
+pub struct GBufferShader<'a> {
+    model: &'a Model,
+}
+
+impl<'a> GBufferShader<'a> {
+    #[allow(dead_code)]
+    pub fn new(model: &'a Model) -> GBufferShader<'a> {
+        GBufferShader { model: model }
+    }
+}
+
+/// Varyings: the texture coordinates and the model space position.
+impl<'a> Shader for GBufferShader<'a> {
+    type Varyings = (Vec2f, Vec3f);
+    fn vertex(&self, camera: &Camera, model: &Model, iface: i32, nthvert: i32) -> (Vec4f, (Vec2f, Vec3f)) {
+        let v = model.face_vert(iface, nthvert);
+        (camera.viewport * camera.projection * camera.modelview * v.embed(1.0), (model.uv(iface as usize, nthvert as usize), v))
+    }
+    fn fragment(&self, &(uv, _): &(Vec2f, Vec3f), color: &mut Color) -> bool {
+        *color = self.model.diffuse(uv);
+        false
+    }
+    fn fragment_outputs(&self, &(uv, pos): &(Vec2f, Vec3f), out: &mut FragmentOutputs) -> bool {
+        let c = self.model.diffuse(uv);
+        out.set(GBuffer::ALBEDO, [c[0] as f32 / 255.0, c[1] as f32 / 255.0, c[2] as f32 / 255.0, 1.0]);
+        out.set_vec(GBuffer::NORMAL, self.model.normal(uv).normalize(), 0.0);
+        out.set_scalar(GBuffer::SPECULAR, self.model.specular(uv));
+        out.set_vec(GBuffer::POSITION, pos, 1.0);
+        false
+    }
+    fn early_z(&self) -> bool { true }
+}
+
+/// A shader for `Pipeline::full_screen`, run once for every pixel of the
+/// output. It reads whatever it was given, a `GBuffer` for instance.
+pub trait ScreenShader {
+    /// Colour of the pixel `x`, `y`, blue, green, red, alpha. Returning true
+    /// leaves the pixel alone.
+    fn fragment(&self, x: i32, y: i32, color: &mut [f32; 4]) -> bool;
+}
+
+/// Lighting pass of deferred shading: Phong shading of the `GBuffer` by any
+/// number of lights, in model space like the geometry pass.
+pub struct DeferredLighting<'a> {
+    gbuffer: &'a GBuffer,
+    lights: &'a [Light],
+    eye: Vec3f,
+    occlusion: Option<&'a Attachment>,
+    /// light reaching every surface, whatever its orientation
+    pub ambient: f32,
+}
+
+impl<'a> DeferredLighting<'a> {
+    #[allow(dead_code)]
+    pub fn new(gbuffer: &'a GBuffer, lights: &'a [Light], eye: Vec3f) -> DeferredLighting<'a> {
+        DeferredLighting { gbuffer: gbuffer, lights: lights, eye: eye, occlusion: None, ambient: 0.08 }
+    }
+    /// Scale the ambient term by `occlusion`, from `Ssao::occlusion` on the
+    /// depth of the geometry pass.
+    #[allow(dead_code)]
+    pub fn with_occlusion(mut self, occlusion: &'a Attachment) -> DeferredLighting<'a> {
+        self.occlusion = Some(occlusion);
+        self
+    }
+}
+
+impl<'a> ScreenShader for DeferredLighting<'a> {
+    fn fragment(&self, x: i32, y: i32, color: &mut [f32; 4]) -> bool {
+        if !self.gbuffer.covered(x, y) {
+            return true;
+        }
+        let albedo = self.gbuffer.albedo(x, y);
+        let n = self.gbuffer.normal(x, y);
+        let p = self.gbuffer.position(x, y);
+        let v = (self.eye - p).normalize();
+        let exp = self.gbuffer.specular(x, y);
+        let ambient = self.ambient * self.occlusion.map_or(1.0, |ao| ao.get(x, y)[0]);
+        let mut sum = [ambient; 3];
+        for light in self.lights.iter() {
+            let (l, c) = light.incident(p);
+            let diff = (n * l).max(0.0);
+            if diff <= 0.0 {
+                continue;
+            }
+            let r = n * (2.0 * (n * l)) - l;
+            let spec = (r * v).max(0.0).powf(exp);
+            // blue, green, red
+            for i in 0..3 {
+                sum[i] += c[2 - i] * (diff + 0.6 * spec);
+            }
+        }
+        *color = [albedo[0] * sum[0], albedo[1] * sum[1], albedo[2] * sum[2], 1.0];
+        false
+    }
+}
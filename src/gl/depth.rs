@@ -0,0 +1,126 @@
+/// Comparison used by the depth (and stencil) test. The test passes when
+/// `value <func> stored` holds, `value` being the incoming fragment's.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum CompareFunc {
+    Never,
+    Less,
+    Equal,
+    LEqual,
+    Greater,
+    NotEqual,
+    GEqual,
+    Always,
+}
+
+impl CompareFunc {
+    pub fn test<T: PartialOrd>(&self, value: T, stored: T) -> bool {
+        match *self {
+            CompareFunc::Never => false,
+            CompareFunc::Less => value < stored,
+            CompareFunc::Equal => value == stored,
+            CompareFunc::LEqual => value <= stored,
+            CompareFunc::Greater => value > stored,
+            CompareFunc::NotEqual => value != stored,
+            CompareFunc::GEqual => value >= stored,
+            CompareFunc::Always => true,
+        }
+    }
+}
+
+/// Depth test configuration of a `Pipeline`.
+///
+/// The depth a fragment is tested with is its window z, between 0 and the
+/// depth of the frustum, remapped linearly to `near..far`. The default keeps
+/// window z as is, with larger values closer to the viewer.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct DepthState {
+    pub func: CompareFunc,
+    /// whether fragments that pass store their depth
+    pub write: bool,
+    /// value `Pipeline::clear_depth` fills the buffers with
+    pub clear: f32,
+    /// depth stored for window z = 0
+    pub near: f32,
+    /// depth stored for window z = frustum depth
+    pub far: f32,
+}
+
+impl DepthState {
+    pub fn new(depth: f32) -> DepthState {
+        DepthState {
+            func: CompareFunc::GEqual,
+            write: true,
+            clear: std::f32::MIN,
+            near: 0.0,
+            far: depth,
+        }
+    }
+    /// The opposite convention: the range is flipped so that closer
+    /// fragments store smaller values, the buffers are cleared to the
+    /// largest value and the smallest depth wins.
+    #[allow(dead_code)]
+    pub fn reversed(depth: f32) -> DepthState {
+        DepthState {
+            func: CompareFunc::LEqual,
+            write: true,
+            clear: std::f32::MAX,
+            near: depth,
+            far: 0.0,
+        }
+    }
+    /// Test the depth only, leave the buffer untouched.
+    #[allow(dead_code)]
+    pub fn read_only(mut self) -> DepthState {
+        self.write = false;
+        self
+    }
+    /// Where the stored depth `z` lies in the range, 0 at the far end and 1
+    /// at the near end whatever the convention. `None` for the clear value
+    /// and anything out of the range.
+    pub fn closeness(&self, z: f32) -> Option<f32> {
+        let (lo, hi) = (self.near.min(self.far), self.near.max(self.far));
+        if z == self.clear || z < lo || z > hi {
+            return None;
+        }
+        match self.func {
+            CompareFunc::Less | CompareFunc::LEqual => Some((hi - z) / (hi - lo)),
+            _ => Some((z - lo) / (hi - lo)),
+        }
+    }
+}
+
+/// Depth offset of a draw, like `glPolygonOffset`: `constant` plus `slope`
+/// times the largest screen space slope of the triangle's window z. Positive
+/// values move the fragments towards the viewer with the default depth
+/// state: a wireframe drawn over its mesh wants a small positive bias, a
+/// shadow map a negative one so that lit surfaces do not shadow themselves.
+/// Lines and points drawn on their own only get `constant`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct DepthBias {
+    pub constant: f32,
+    pub slope: f32,
+}
+
+impl DepthBias {
+    pub fn new() -> DepthBias {
+        DepthBias { constant: 0.0, slope: 0.0 }
+    }
+    #[allow(dead_code)]
+    pub fn with(constant: f32, slope: f32) -> DepthBias {
+        DepthBias { constant: constant, slope: slope }
+    }
+    /// Offset for a triangle whose window z changes by up to `slope` per pixel.
+    pub fn offset(&self, slope: f32) -> f32 {
+        if self.slope == 0.0 {
+            self.constant
+        } else {
+            self.constant + self.slope * slope
+        }
+    }
+}
+
+impl Default for DepthBias {
+    fn default() -> DepthBias {
+        DepthBias::new()
+    }
+}
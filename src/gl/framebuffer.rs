@@ -0,0 +1,304 @@
+use gl::*;
+
+/// Storage format of a colour attachment. The channels are kept in the byte
+/// order of the images, blue, green, red then alpha, so that an `Rgba8`
+/// attachment is laid out like an `RGBA` `TGAImage`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Format {
+    /// four bytes per pixel, `0..255` mapped to `0..1`
+    Rgba8,
+    /// one float per pixel, the red channel of what is written
+    R32F,
+    /// four floats per pixel
+    Rgba32F,
+}
+
+impl Format {
+    pub fn channels(&self) -> usize {
+        match *self {
+            Format::Rgba8 | Format::Rgba32F => 4,
+            Format::R32F => 1,
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+enum Storage {
+    Bytes(Vec<u8>),
+    Floats(Vec<f32>),
+}
+
+/// One colour buffer of a `Framebuffer`.
+///
+/// Values are read and written as blue, green, red, alpha normalized to
+/// `0..1`; an `R32F` attachment reads back as a gray with an opaque alpha.
+#[derive(Debug, Clone)]
+pub struct Attachment {
+    width: i32,
+    height: i32,
+    format: Format,
+    data: Storage,
+}
+
+impl Attachment {
+    /// A black, transparent attachment.
+    pub fn new(width: i32, height: i32, format: Format) -> Attachment {
+        let n = (width * height) as usize * format.channels();
+        Attachment {
+            width: width,
+            height: height,
+            format: format,
+            data: match format {
+                Format::Rgba8 => Storage::Bytes(vec![0; n]),
+                Format::R32F | Format::Rgba32F => Storage::Floats(vec![0.0; n]),
+            },
+        }
+    }
+    /// An `Rgba8` copy of an `RGB` or `RGBA` image, opaque for the former.
+    #[allow(dead_code)]
+    pub fn from_image(image: &TGAImage) -> Attachment {
+        let bytespp = image.get_bytespp();
+        assert!(bytespp == tga_image::RGB as i32 || bytespp == tga_image::RGBA as i32,
+                "Error: Attachment::from_image needs an RGB or RGBA image.");
+        let mut ret = Attachment::new(image.get_width(), image.get_height(), Format::Rgba8);
+        for y in 0..image.get_height() {
+            for x in 0..image.get_width() {
+                let raw = image.get(x, y).raw();
+                let alpha = if bytespp == tga_image::RGBA as i32 { raw[3] } else { 255 };
+                ret.bytes_mut().unwrap()[((x + y * image.get_width()) * 4) as usize..][..4].copy_from_slice(&[raw[0], raw[1], raw[2], alpha]);
+            }
+        }
+        ret
+    }
+    #[allow(dead_code)]
+    pub fn get_width(&self) -> i32 { self.width }
+    #[allow(dead_code)]
+    pub fn get_height(&self) -> i32 { self.height }
+    #[allow(dead_code)]
+    pub fn format(&self) -> Format { self.format }
+    /// The raw bytes of an `Rgba8` attachment.
+    #[allow(dead_code)]
+    pub fn bytes(&self) -> Option<&[u8]> {
+        match self.data {
+            Storage::Bytes(ref b) => Some(b),
+            Storage::Floats(..) => None,
+        }
+    }
+    /// The raw floats of an `R32F` or `Rgba32F` attachment.
+    #[allow(dead_code)]
+    pub fn floats(&self) -> Option<&[f32]> {
+        match self.data {
+            Storage::Floats(ref f) => Some(f),
+            Storage::Bytes(..) => None,
+        }
+    }
+    pub fn bytes_mut(&mut self) -> Option<&mut [u8]> {
+        match self.data {
+            Storage::Bytes(ref mut b) => Some(b),
+            Storage::Floats(..) => None,
+        }
+    }
+    pub fn floats_mut(&mut self) -> Option<&mut [f32]> {
+        match self.data {
+            Storage::Floats(ref mut f) => Some(f),
+            Storage::Bytes(..) => None,
+        }
+    }
+
+    #[allow(dead_code)]
+    pub fn get(&self, x: i32, y: i32) -> [f32; 4] {
+        let n = self.format.channels();
+        let i = (x + y * self.width) as usize * n;
+        match self.data {
+            Storage::Bytes(ref b) => [b[i] as f32 / 255.0, b[i + 1] as f32 / 255.0, b[i + 2] as f32 / 255.0, b[i + 3] as f32 / 255.0],
+            Storage::Floats(ref f) if n == 1 => [f[i], f[i], f[i], 1.0],
+            Storage::Floats(ref f) => [f[i], f[i + 1], f[i + 2], f[i + 3]],
+        }
+    }
+    /// Store `value`; `Rgba8` clamps it to `0..1`.
+    pub fn set(&mut self, x: i32, y: i32, value: [f32; 4]) {
+        let n = self.format.channels();
+        let i = (x + y * self.width) as usize * n;
+        match self.data {
+            Storage::Bytes(ref mut b) => {
+                for c in 0..4 {
+                    b[i + c] = (value[c].max(0.0).min(1.0) * 255.0 + 0.5) as u8;
+                }
+            }
+            Storage::Floats(ref mut f) if n == 1 => f[i] = value[2],
+            Storage::Floats(ref mut f) => f[i..i + 4].copy_from_slice(&value),
+        }
+    }
+    /// Fill the attachment with `value`.
+    pub fn clear(&mut self, value: [f32; 4]) {
+        for y in 0..self.height {
+            for x in 0..self.width {
+                self.set(x, y, value);
+            }
+        }
+    }
+
+    /// Copy to an image to write out: `R32F` becomes a grayscale image, the
+    /// others `RGBA`. Floats are clamped to `0..1`.
+    #[allow(dead_code)]
+    pub fn to_image(&self) -> TGAImage {
+        let gray = self.format == Format::R32F;
+        let mut image = TGAImage::with_info(self.width as isize, self.height as isize, if gray { tga_image::GRAYSCALE } else { tga_image::RGBA });
+        let to_byte = |v: f32| (v.max(0.0).min(1.0) * 255.0 + 0.5) as u8;
+        match self.data {
+            Storage::Bytes(ref b) => image.buffer().copy_from_slice(b),
+            Storage::Floats(ref f) => {
+                for (dst, &v) in image.buffer().iter_mut().zip(f.iter()) {
+                    *dst = to_byte(v);
+                }
+            }
+        }
+        image
+    }
+}
+
+/// A render target made of a depth buffer, an optional stencil buffer and
+/// any number of colour attachments, all of the same size. `Pipeline::triangle`
+/// and `Pipeline::draw` take it as their `Target`.
+#[derive(Debug, Clone)]
+pub struct Framebuffer {
+    width: i32,
+    height: i32,
+    depth: Vec<f32>,
+    stencil: Option<Vec<u8>>,
+    colors: Vec<Attachment>,
+}
+
+impl Framebuffer {
+    /// A depth only framebuffer, its depth cleared to `f32::MIN`.
+    pub fn new(width: i32, height: i32) -> Framebuffer {
+        Framebuffer {
+            width: width,
+            height: height,
+            depth: vec![std::f32::MIN; (width * height) as usize],
+            stencil: None,
+            colors: vec![],
+        }
+    }
+    /// Add a stencil buffer of one byte per pixel, cleared to 0.
+    #[allow(dead_code)]
+    pub fn with_stencil(mut self) -> Framebuffer {
+        self.stencil = Some(vec![0; (self.width * self.height) as usize]);
+        self
+    }
+    /// Add a colour attachment of `format`.
+    #[allow(dead_code)]
+    pub fn with_color(mut self, format: Format) -> Framebuffer {
+        let attachment = Attachment::new(self.width, self.height, format);
+        self.attach(attachment);
+        self
+    }
+    /// Add `attachment` after the others and return its index. It must have
+    /// the size of the framebuffer, which holds at most `MAX_OUTPUTS`.
+    pub fn attach(&mut self, attachment: Attachment) -> usize {
+        assert!(attachment.width == self.width && attachment.height == self.height,
+                "Error: Framebuffer::attach {}x{} attachment does not match the {}x{} framebuffer.",
+                attachment.width, attachment.height, self.width, self.height);
+        assert!(self.colors.len() < MAX_OUTPUTS,
+                "Error: Framebuffer::attach a framebuffer holds at most {} colour attachments.", MAX_OUTPUTS);
+        self.colors.push(attachment);
+        self.colors.len() - 1
+    }
+    #[allow(dead_code)]
+    pub fn get_width(&self) -> i32 { self.width }
+    #[allow(dead_code)]
+    pub fn get_height(&self) -> i32 { self.height }
+    #[allow(dead_code)]
+    pub fn color(&self, i: usize) -> &Attachment { &self.colors[i] }
+    #[allow(dead_code)]
+    pub fn color_mut(&mut self, i: usize) -> &mut Attachment { &mut self.colors[i] }
+    #[allow(dead_code)]
+    pub fn colors(&self) -> &[Attachment] { &self.colors }
+    #[allow(dead_code)]
+    pub fn depth(&self) -> &[f32] { &self.depth }
+    #[allow(dead_code)]
+    pub fn depth_mut(&mut self) -> &mut [f32] { &mut self.depth }
+    #[allow(dead_code)]
+    pub fn stencil(&self) -> Option<&[u8]> { self.stencil.as_ref().map(|s| &s[..]) }
+    pub fn buffers(&mut self) -> (&mut [Attachment], &mut [f32], Option<&mut [u8]>) {
+        (&mut self.colors, &mut self.depth, self.stencil.as_mut().map(|s| &mut s[..]))
+    }
+
+    /// Fill every colour attachment with `value`.
+    #[allow(dead_code)]
+    pub fn clear_color(&mut self, value: [f32; 4]) {
+        for attachment in self.colors.iter_mut() {
+            attachment.clear(value);
+        }
+    }
+    /// Fill the depth buffer with `value`, usually `Pipeline::depth.clear`.
+    #[allow(dead_code)]
+    pub fn clear_depth(&mut self, value: f32) {
+        for z in self.depth.iter_mut() {
+            *z = value;
+        }
+    }
+    #[allow(dead_code)]
+    pub fn clear_stencil(&mut self, value: u8) {
+        if let Some(ref mut stencil) = self.stencil {
+            for s in stencil.iter_mut() {
+                *s = value;
+            }
+        }
+    }
+}
+
+/// Most colour attachments a fragment can write to.
+pub const MAX_OUTPUTS: usize = 8;
+
+/// What a fragment writes to the colour attachments of the target, see
+/// `Shader::fragment_outputs`. Values are blue, green, red, alpha like the
+/// attachments; an attachment given no value gets the broadcast colour if
+/// there is one and is left alone otherwise. With both an 8 bit and an HDR
+/// broadcast colour, `Rgba8` attachments take the former and float ones the
+/// latter.
+#[derive(Debug, Clone, Copy)]
+pub struct FragmentOutputs {
+    color: Option<Color>,
+    hdr: Option<[f32; 4]>,
+    values: [Option<[f32; 4]>; MAX_OUTPUTS],
+}
+
+impl FragmentOutputs {
+    pub fn new() -> FragmentOutputs {
+        FragmentOutputs { color: None, hdr: None, values: [None; MAX_OUTPUTS] }
+    }
+    /// Write `color` to every attachment, as `Shader::fragment` does.
+    pub fn broadcast(&mut self, color: Color) {
+        self.color = Some(color);
+    }
+    /// Write `color` to every attachment, unclamped in the float ones.
+    #[allow(dead_code)]
+    pub fn broadcast_hdr(&mut self, color: ColorF) {
+        self.hdr = Some(color.bgra());
+    }
+    /// Write `value` to the attachment `i`.
+    pub fn set(&mut self, i: usize, value: [f32; 4]) {
+        self.values[i] = Some(value);
+    }
+    /// Write a vector to the attachment `i`, as `[x, y, z, w]`.
+    #[allow(dead_code)]
+    pub fn set_vec(&mut self, i: usize, v: Vec3f, w: f32) {
+        self.set(i, [v.x, v.y, v.z, w]);
+    }
+    /// Write a single value to the attachment `i`, what an `R32F` stores.
+    #[allow(dead_code)]
+    pub fn set_scalar(&mut self, i: usize, value: f32) {
+        self.set(i, [value, value, value, 1.0]);
+    }
+    pub fn color(&self) -> Option<Color> { self.color }
+    pub fn hdr(&self) -> Option<[f32; 4]> { self.hdr }
+    /// The value written to the attachment `i`, `None` past `MAX_OUTPUTS`.
+    pub fn get(&self, i: usize) -> Option<[f32; 4]> { self.values.get(i).and_then(|v| *v) }
+}
+
+impl Default for FragmentOutputs {
+    fn default() -> FragmentOutputs {
+        FragmentOutputs::new()
+    }
+}
@@ -0,0 +1,208 @@
+use gl::*;
+use std::io::prelude::*;
+use std::ops::{Add, Mul};
+
+/// A linear colour with float channels, not limited to `0..1`, for shaders
+/// that write to `Rgba32F` attachments.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ColorF {
+    pub r: f32,
+    pub g: f32,
+    pub b: f32,
+    pub a: f32,
+}
+
+impl ColorF {
+    /// Opaque.
+    pub fn new(r: f32, g: f32, b: f32) -> ColorF {
+        ColorF { r: r, g: g, b: b, a: 1.0 }
+    }
+    #[allow(dead_code)]
+    pub fn rgba(r: f32, g: f32, b: f32, a: f32) -> ColorF {
+        ColorF { r: r, g: g, b: b, a: a }
+    }
+    /// `color` normalized to `0..1`, opaque unless it has an alpha.
+    #[allow(dead_code)]
+    pub fn from_color(color: Color) -> ColorF {
+        let raw = color.raw();
+        match color.nbytes() {
+            1 => ColorF::new(raw[0] as f32 / 255.0, raw[0] as f32 / 255.0, raw[0] as f32 / 255.0),
+            n => ColorF::rgba(raw[2] as f32 / 255.0, raw[1] as f32 / 255.0, raw[0] as f32 / 255.0, if n == 4 { raw[3] as f32 / 255.0 } else { 1.0 }),
+        }
+    }
+    /// In the channel order of the attachments.
+    pub fn bgra(&self) -> [f32; 4] {
+        [self.b, self.g, self.r, self.a]
+    }
+    pub fn from_bgra(v: [f32; 4]) -> ColorF {
+        ColorF::rgba(v[2], v[1], v[0], v[3])
+    }
+    /// Clamped to `0..1` and quantized.
+    #[allow(dead_code)]
+    pub fn to_color(&self) -> Color {
+        let to_byte = |v: f32| (v.max(0.0).min(1.0) * 255.0 + 0.5) as u8;
+        Color::with_color(RGBAColor(to_byte(self.r), to_byte(self.g), to_byte(self.b), to_byte(self.a)))
+    }
+    /// Relative luminance, Rec. 709 weights.
+    #[allow(dead_code)]
+    pub fn luminance(&self) -> f32 {
+        0.2126 * self.r + 0.7152 * self.g + 0.0722 * self.b
+    }
+}
+
+/// Scales the colour, not the alpha.
+impl Mul<f32> for ColorF {
+    type Output = ColorF;
+    fn mul(self, rhs: f32) -> ColorF {
+        ColorF::rgba(self.r * rhs, self.g * rhs, self.b * rhs, self.a)
+    }
+}
+
+/// Component-wise, for lights filtered by a surface.
+impl Mul<ColorF> for ColorF {
+    type Output = ColorF;
+    fn mul(self, rhs: ColorF) -> ColorF {
+        ColorF::rgba(self.r * rhs.r, self.g * rhs.g, self.b * rhs.b, self.a * rhs.a)
+    }
+}
+
+/// Adds the colours, keeps the alpha of the left side.
+impl Add for ColorF {
+    type Output = ColorF;
+    fn add(self, rhs: ColorF) -> ColorF {
+        ColorF::rgba(self.r + rhs.r, self.g + rhs.g, self.b + rhs.b, self.a)
+    }
+}
+
+/// Curve bringing the unbounded HDR values down to `0..1`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ToneOperator {
+    /// Only clamps.
+    Clamp,
+    /// `x / (1 + x)`
+    Reinhard,
+    /// Krzysztof Narkowicz's fit of the ACES filmic curve.
+    AcesFilmic,
+}
+
+impl ToneOperator {
+    pub fn apply(&self, x: f32) -> f32 {
+        let x = x.max(0.0);
+        let y = match *self {
+            ToneOperator::Clamp => x,
+            ToneOperator::Reinhard => x / (1.0 + x),
+            ToneOperator::AcesFilmic => (x * (2.51 * x + 0.03)) / (x * (2.43 * x + 0.59) + 0.14),
+        };
+        y.min(1.0)
+    }
+}
+
+/// Resolve of an HDR attachment to an 8 bit image: every colour channel is
+/// multiplied by `exposure`, mapped with `operator` and quantized. The alpha
+/// is only clamped.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ToneMapping {
+    pub operator: ToneOperator,
+    pub exposure: f32,
+}
+
+impl ToneMapping {
+    pub fn new(operator: ToneOperator) -> ToneMapping {
+        ToneMapping { operator: operator, exposure: 1.0 }
+    }
+    /// Exposure given in stops, `2^stops`.
+    #[allow(dead_code)]
+    pub fn stops(mut self, stops: f32) -> ToneMapping {
+        self.exposure = 2f32.powf(stops);
+        self
+    }
+    pub fn map(&self, color: ColorF) -> ColorF {
+        let op = self.operator;
+        ColorF::rgba(op.apply(color.r * self.exposure), op.apply(color.g * self.exposure), op.apply(color.b * self.exposure), color.a.max(0.0).min(1.0))
+    }
+
+    /// Map `hdr` into `image`, which must have the same size and one, three
+    /// or four bytes per pixel; a grayscale image gets the luminance.
+    #[allow(dead_code)]
+    pub fn resolve(&self, hdr: &Attachment, image: &mut TGAImage) {
+        assert!(image.get_width() == hdr.get_width() && image.get_height() == hdr.get_height(),
+                "Error: ToneMapping::resolve image does not match the attachment.");
+        let bytespp = image.get_bytespp();
+        let to_byte = |v: f32| (v * 255.0 + 0.5) as u8;
+        for y in 0..hdr.get_height() {
+            for x in 0..hdr.get_width() {
+                let c = ColorF::from_bgra(hdr.get(x, y));
+                let color = if bytespp == tga_image::GRAYSCALE as i32 {
+                    let l = self.map(ColorF::new(c.luminance(), c.luminance(), c.luminance()));
+                    Color::grayscale(to_byte(l.r))
+                } else {
+                    let m = self.map(c);
+                    if bytespp == tga_image::RGBA as i32 {
+                        Color::with_color(RGBAColor(to_byte(m.r), to_byte(m.g), to_byte(m.b), to_byte(m.a)))
+                    } else {
+                        Color::with_color(RGBColor(to_byte(m.r), to_byte(m.g), to_byte(m.b)))
+                    }
+                };
+                image.set(x, y, color);
+            }
+        }
+    }
+}
+
+fn create(filename: &str) -> Result<std::io::BufWriter<std::fs::File>, &'static str> {
+    match std::fs::File::create(std::path::Path::new(filename)) {
+        Ok(file) => Ok(std::io::BufWriter::new(file)),
+        Err(_) => Err("Error: can't create the file."),
+    }
+}
+
+impl Attachment {
+    /// Write the raw values as a Portable Float Map, `Pf` for an `R32F`
+    /// attachment and `PF` (red, green, blue) otherwise. Like the
+    /// framebuffer, the file starts with the bottom row.
+    #[allow(dead_code)]
+    pub fn write_pfm(&self, filename: &str) -> Result<(), &'static str> {
+        let gray = self.format() == Format::R32F;
+        let mut file = create(filename)?;
+        let mut data = format!("{}\n{} {}\n-1.0\n", if gray { "Pf" } else { "PF" }, self.get_width(), self.get_height()).into_bytes();
+        for y in 0..self.get_height() {
+            for x in 0..self.get_width() {
+                let c = ColorF::from_bgra(self.get(x, y));
+                let values = if gray { vec![c.r] } else { vec![c.r, c.g, c.b] };
+                for v in values {
+                    data.extend_from_slice(&v.to_le_bytes());
+                }
+            }
+        }
+        file.write_all(&data).map_err(|_| "Error: Attachment::write_pfm can't dump the file.")
+    }
+
+    /// Write the values as a Radiance `.hdr` file, without run length
+    /// encoding. Negative values are written as 0.
+    #[allow(dead_code)]
+    pub fn write_hdr(&self, filename: &str) -> Result<(), &'static str> {
+        let mut file = create(filename)?;
+        let (w, h) = (self.get_width(), self.get_height());
+        let mut data = format!("#?RADIANCE\nFORMAT=32-bit_rle_rgbe\n\n-Y {} +X {}\n", h, w).into_bytes();
+        // the file starts with the top row
+        for y in (0..h).rev() {
+            for x in 0..w {
+                let c = ColorF::from_bgra(self.get(x, y));
+                data.extend_from_slice(&rgbe(c.r.max(0.0), c.g.max(0.0), c.b.max(0.0)));
+            }
+        }
+        file.write_all(&data).map_err(|_| "Error: Attachment::write_hdr can't dump the file.")
+    }
+}
+
+/// Shared exponent encoding of the Radiance format.
+fn rgbe(r: f32, g: f32, b: f32) -> [u8; 4] {
+    let m = r.max(g).max(b);
+    if m < 1.0e-32 {
+        return [0; 4];
+    }
+    // m = f * 2^e with f in 0.5..1
+    let e = m.log2().floor() as i32 + 1;
+    let scale = 256.0 / 2f32.powi(e);
+    [(r * scale).min(255.0) as u8, (g * scale).min(255.0) as u8, (b * scale).min(255.0) as u8, (e + 128) as u8]
+}
@@ -0,0 +1,72 @@
+use gl::*;
+
+/// Where a `Light` shines from. Positions and directions are in the space
+/// the shaders light in.
+#[derive(Debug, Clone, Copy)]
+pub enum LightKind {
+    /// Infinitely far away, `dir` points towards the light.
+    Directional { dir: Vec3f },
+    /// Shines in every direction from `pos`, fading out to nothing at
+    /// `range`.
+    Point { pos: Vec3f, range: f32 },
+    /// A point light limited to a cone around `dir`, which points away from
+    /// the light. `inner` and `outer` are the cosines of the half angles
+    /// inside of which the light is full and outside of which it is gone.
+    Spot { pos: Vec3f, dir: Vec3f, range: f32, inner: f32, outer: f32 },
+}
+
+/// A light of the scene. The colour is red, green, blue; what reaches the
+/// surfaces is the colour times the intensity, which may go above one.
+#[derive(Debug, Clone, Copy)]
+pub struct Light {
+    pub kind: LightKind,
+    pub color: Vec3f,
+    pub intensity: f32,
+}
+
+impl Light {
+    #[allow(dead_code)]
+    pub fn directional(dir: Vec3f, color: Vec3f) -> Light {
+        Light { kind: LightKind::Directional { dir: dir.normalize() }, color: color, intensity: 1.0 }
+    }
+    #[allow(dead_code)]
+    pub fn point(pos: Vec3f, color: Vec3f, range: f32) -> Light {
+        Light { kind: LightKind::Point { pos: pos, range: range }, color: color, intensity: 1.0 }
+    }
+    /// A spot at `pos` shining along `dir`; the angles, in radians, are
+    /// measured from `dir` to the edges of the full and of the fading cone.
+    #[allow(dead_code)]
+    pub fn spot(pos: Vec3f, dir: Vec3f, color: Vec3f, range: f32, inner: f32, outer: f32) -> Light {
+        let kind = LightKind::Spot { pos: pos, dir: dir.normalize(), range: range, inner: inner.cos(), outer: outer.max(inner).cos() };
+        Light { kind: kind, color: color, intensity: 1.0 }
+    }
+    #[allow(dead_code)]
+    pub fn with_intensity(mut self, intensity: f32) -> Light {
+        self.intensity = intensity;
+        self
+    }
+    /// Direction from `p` towards the light and the colour reaching `p`.
+    pub fn incident(&self, p: Vec3f) -> (Vec3f, Vec3f) {
+        let color = self.color * self.intensity;
+        match self.kind {
+            LightKind::Directional { dir } => (dir, color),
+            LightKind::Point { pos, range } => {
+                let (l, fade) = attenuation(pos - p, range);
+                (l, color * fade)
+            }
+            LightKind::Spot { pos, dir, range, inner, outer } => {
+                let (l, fade) = attenuation(pos - p, range);
+                let cos = -(l * dir);
+                let cone = if inner > outer { ((cos - outer) / (inner - outer)).max(0.0).min(1.0) } else if cos >= inner { 1.0 } else { 0.0 };
+                (l, color * (fade * cone * cone * (3.0 - 2.0 * cone)))
+            }
+        }
+    }
+}
+
+/// Unit vector along `d` and how much of a light `d` away with `range`
+/// is left.
+fn attenuation(d: Vec3f, range: f32) -> (Vec3f, f32) {
+    let fade = (1.0 - d.norm() as f32 / range).max(0.0);
+    (d.normalize(), fade * fade)
+}
@@ -0,0 +1,189 @@
+use gl::*;
+
+/// How the ends of a line drawn with `line_aa` are finished.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum LineCap {
+    /// stops at the end points
+    Butt,
+    /// goes on for half the width past the end points
+    Square,
+    /// half a disc around each end point
+    Round,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct LineStyle {
+    /// in pixels
+    pub width: f32,
+    pub cap: LineCap,
+}
+
+impl LineStyle {
+    /// One pixel wide, butt ends.
+    pub fn new() -> LineStyle {
+        LineStyle { width: 1.0, cap: LineCap::Butt }
+    }
+    #[allow(dead_code)]
+    pub fn with(width: f32, cap: LineCap) -> LineStyle {
+        LineStyle { width: width, cap: cap }
+    }
+}
+
+impl Default for LineStyle {
+    fn default() -> LineStyle {
+        LineStyle::new()
+    }
+}
+
+/// Draw an anti-aliased line from `p0` to `p1` on top of `image`.
+///
+/// The end points are in pixels with the pixel centres at `.5`, as for the
+/// rasterizer. Every pixel touched is blended with `color` in proportion to
+/// how much of it the line covers, times the alpha of `color` if it has one.
+/// Thin lines with butt ends use Xiaolin Wu's algorithm; wider lines and the
+/// other caps use the distance from the pixel centre to the line. There is
+/// no depth test, the line is drawn over everything; see `line_aa_depth`.
+#[allow(dead_code)]
+pub fn line_aa(p0: Vec2f, p1: Vec2f, image: &mut TGAImage, color: Color, style: &LineStyle) {
+    draw(p0, p1, image, None, color, style);
+}
+
+/// `line_aa` hidden by the scene in `zbuffer`, for overlays such as normals
+/// or bounding boxes. The `z` of the ends is a value of the depth buffer,
+/// interpolated along the line; the pixels where `func` fails against
+/// `zbuffer` are left alone. The depth buffer is not written.
+#[allow(dead_code)]
+pub fn line_aa_depth(p0: Vec3f, p1: Vec3f, image: &mut TGAImage, zbuffer: &[f32], func: CompareFunc, color: Color, style: &LineStyle) {
+    assert!(zbuffer.len() == (image.get_width() * image.get_height()) as usize,
+            "Error: line_aa_depth depth buffer does not match the image.");
+    let (a, b) = (Vec2f::new(p0.x, p0.y), Vec2f::new(p1.x, p1.y));
+    let test = DepthTest { p0: a, d: b - a, z0: p0.z, z1: p1.z, zbuffer: zbuffer, func: func };
+    draw(a, b, image, Some(&test), color, style);
+}
+
+fn draw(p0: Vec2f, p1: Vec2f, image: &mut TGAImage, depth: Option<&DepthTest>, color: Color, style: &LineStyle) {
+    if style.width <= 1.0 && style.cap == LineCap::Butt {
+        wu(p0, p1, image, depth, color, style.width.max(0.0));
+    } else {
+        wide(p0, p1, image, depth, color, style);
+    }
+}
+
+/// The depth test of `line_aa_depth`.
+struct DepthTest<'a> {
+    p0: Vec2f,
+    d: Vec2f,
+    z0: f32,
+    z1: f32,
+    zbuffer: &'a [f32],
+    func: CompareFunc,
+}
+
+impl<'a> DepthTest<'a> {
+    /// Whether the line is visible at the centre of the pixel `x`, `y` of an
+    /// image `width` wide. Window z is affine in screen space, so it goes
+    /// linearly along the line.
+    fn passes(&self, x: i32, y: i32, width: i32) -> bool {
+        let c = Vec2f::new(x as f32 + 0.5, y as f32 + 0.5) - self.p0;
+        let len2 = self.d.x * self.d.x + self.d.y * self.d.y;
+        let t = if len2 > 0.0 { ((c.x * self.d.x + c.y * self.d.y) / len2).max(0.0).min(1.0) } else { 0.0 };
+        self.func.test(self.z0 + (self.z1 - self.z0) * t, self.zbuffer[(x + y * width) as usize])
+    }
+}
+
+fn fpart(x: f32) -> f32 {
+    x - x.floor()
+}
+
+/// Blend `color` into the pixel `x`, `y` with weight `coverage`.
+fn plot(image: &mut TGAImage, depth: Option<&DepthTest>, x: i32, y: i32, color: Color, coverage: f32) {
+    if x < 0 || y < 0 || x >= image.get_width() || y >= image.get_height() || coverage <= 0.0 {
+        return;
+    }
+    if depth.map_or(false, |d| !d.passes(x, y, image.get_width())) {
+        return;
+    }
+    let alpha = if color.nbytes() == 4 { color[3] as f32 / 255.0 } else { 1.0 };
+    let a = (coverage * alpha).min(1.0);
+    let src = color.raw();
+    let mut dst = image.get(x, y);
+    for c in 0..image.get_bytespp() as usize {
+        dst[c] = (dst[c] as f32 + (src[c] as f32 - dst[c] as f32) * a + 0.5) as u8;
+    }
+    image.set(x, y, dst);
+}
+
+fn wu(p0: Vec2f, p1: Vec2f, image: &mut TGAImage, depth: Option<&DepthTest>, color: Color, weight: f32) {
+    // move the pixel centres to the integers
+    let (mut x0, mut y0, mut x1, mut y1) = (p0.x - 0.5, p0.y - 0.5, p1.x - 0.5, p1.y - 0.5);
+    let steep = (y1 - y0).abs() > (x1 - x0).abs();
+    if steep {
+        std::mem::swap(&mut x0, &mut y0);
+        std::mem::swap(&mut x1, &mut y1);
+    }
+    if x0 > x1 {
+        std::mem::swap(&mut x0, &mut x1);
+        std::mem::swap(&mut y0, &mut y1);
+    }
+    let gradient = if x1 == x0 { 1.0 } else { (y1 - y0) / (x1 - x0) };
+    let mut put = |x: i32, y: f32, coverage: f32| {
+        let yi = y.floor() as i32;
+        let (c0, c1) = ((1.0 - fpart(y)) * coverage * weight, fpart(y) * coverage * weight);
+        if steep {
+            plot(image, depth, yi, x, color, c0);
+            plot(image, depth, yi + 1, x, color, c1);
+        } else {
+            plot(image, depth, x, yi, color, c0);
+            plot(image, depth, x, yi + 1, color, c1);
+        }
+    };
+
+    // the end points only get the part of their pixel the line reaches
+    let xend = x0.round();
+    let first = xend as i32;
+    put(first, y0 + gradient * (xend - x0), 1.0 - fpart(x0 + 0.5));
+    let mut y = y0 + gradient * (xend - x0) + gradient;
+    let xend = x1.round();
+    let last = xend as i32;
+    if last > first {
+        put(last, y1 + gradient * (xend - x1), fpart(x1 + 0.5));
+    }
+    for x in (first + 1)..last {
+        put(x, y, 1.0);
+        y += gradient;
+    }
+}
+
+fn wide(p0: Vec2f, p1: Vec2f, image: &mut TGAImage, depth: Option<&DepthTest>, color: Color, style: &LineStyle) {
+    let d = p1 - p0;
+    let len = (d.x * d.x + d.y * d.y).sqrt();
+    let u = if len > 0.0 { d * (1.0 / len) } else { Vec2f::new(1, 0) };
+    let half = style.width / 2.0;
+    let ext = if style.cap == LineCap::Butt { 0.0 } else { half };
+    let reach = half + ext + 1.0;
+    let x0 = (p0.x.min(p1.x) - reach).floor() as i32;
+    let x1 = (p0.x.max(p1.x) + reach).ceil() as i32;
+    let y0 = (p0.y.min(p1.y) - reach).floor() as i32;
+    let y1 = (p0.y.max(p1.y) + reach).ceil() as i32;
+    for y in y0.max(0)..y1.min(image.get_height()) {
+        for x in x0.max(0)..x1.min(image.get_width()) {
+            let c = Vec2f::new(x as f32 + 0.5, y as f32 + 0.5) - p0;
+            // along and across the line
+            let s = c.x * u.x + c.y * u.y;
+            let t = c.y * u.x - c.x * u.y;
+            let coverage = match style.cap {
+                LineCap::Round => {
+                    let along = s.max(0.0).min(len);
+                    let dist = ((s - along) * (s - along) + t * t).sqrt();
+                    (half + 0.5 - dist).max(0.0).min(1.0)
+                }
+                _ => {
+                    let across = (half + 0.5 - t.abs()).max(0.0).min(1.0);
+                    let along = (s + ext + 0.5).min(len + ext - s + 0.5).max(0.0).min(1.0);
+                    across * along
+                }
+            };
+            plot(image, depth, x, y, color, coverage);
+        }
+    }
+}
@@ -3,12 +3,41 @@ pub mod geometry;
 pub mod model;
 pub mod tga_image;
 pub mod color;
+#[macro_use]
 pub mod shader;
+pub mod clip;
+pub mod pipeline;
+pub mod msaa;
+pub mod depth;
+pub mod stencil;
+pub mod blend;
+pub mod line;
+pub mod framebuffer;
+pub mod light;
+pub mod deferred;
+pub mod hdr;
+pub mod postprocess;
+pub mod ssao;
+pub mod bake;
 pub use self::tga_image::*;
 pub use self::geometry::*;
 pub use self::model::*;
 pub use self::shader::*;
 pub use self::color::*;
+pub use self::clip::*;
+pub use self::pipeline::*;
+pub use self::msaa::*;
+pub use self::depth::*;
+pub use self::stencil::*;
+pub use self::blend::*;
+pub use self::line::*;
+pub use self::framebuffer::*;
+pub use self::light::*;
+pub use self::deferred::*;
+pub use self::hdr::*;
+pub use self::postprocess::*;
+pub use self::ssao::*;
+pub use self::bake::*;
 use super::std;
 extern crate num;
 
@@ -17,74 +46,6 @@ pub trait Cast {
     fn cast<T>(&self) -> Self::Output;
 }
 
-#[allow(dead_code)]
-pub fn line(mut p0: Vec3i, mut p1: Vec3i, image: &mut TGAImage, color: Color) {
-     let mut steep = false;
-     if (p0.x - p1.x).abs() < (p0.y - p1.y).abs() {
-         std::mem::swap(&mut p0.x, &mut p0.y);
-         std::mem::swap(&mut p1.x, &mut p1.y);
-         steep = true;
-     }
-     if p0.x > p1.x {
-         std::mem::swap(&mut p0, &mut p1);
-     }
-     for x in p0.x..(p1.x + 1) {
-         let t = (x as f32 - p0.x as f32) / (p1.x as f32 - p0.x as f32);
-         let y = p0.y as f32 * (1.0 - t) + p1.y as f32 * t + 0.5;
-         if steep {
-             image.set(y, x, color);
-         } else {
-             image.set(x, y, color);
-         }
-     }
-}
-
-#[allow(non_snake_case)]
-#[allow(dead_code)]
-fn barycentric(A: Vec2f, B: Vec2f, C: Vec2f, P: Vec2f) -> Vec3f {
-    let mut s = [Vec3f::zero();2];
-    for i in 0..2 {
-        s[i][0] = C[i] - A[i];
-        s[i][1] = B[i] - A[i];
-        s[i][2] = A[i] - P[i];
-    }
-    
-    let u = cross::<f32>(s[0], s[1]);
-    if u[2].abs() > 1e-2 {
-        return Vec3f::new(1.0 - (u.x + u.y) / u.z, u.y / u.z, u.x / u.z);
-    }
-    return Vec3f::new(-1.0, 1.0, 1.0)    
-}
-
-pub fn triangle<S: Shader>(pts: &mut [Vec4f], shader: &S, image: &mut TGAImage, zbuffer: &mut Vec<f32>) {
-    let mut bboxmin = Vec2f::new(std::f32::MAX, std::f32::MAX);
-    let mut bboxmax = Vec2f::new(std::f32::MIN, std::f32::MIN);
-    for i in 0..3 {
-        bboxmin[0] = bboxmin[0].min(pts[i][0] / pts[i][3]);
-        bboxmax[0] = bboxmax[0].max(pts[i][0] / pts[i][3]);    
-        bboxmin[1] = bboxmin[1].min(pts[i][1] / pts[i][3]);
-        bboxmax[1] = bboxmax[1].max(pts[i][1] / pts[i][3]);  
-    }
-    
-    let mut color = Color::new();
-    for x in (bboxmin.x as i32)..(bboxmax.x as i32 + 1) {
-        for y in (bboxmin.y as i32)..(bboxmax.y as i32 + 1) {
-            let c = barycentric((pts[0] / pts[0][3]).proj2(), (pts[1] / pts[1][3]).proj2(), (pts[2] / pts[2][3]).proj2(), Vec2i::new(x, y).cast::<f32>());
-            let z = pts[0][2] * c.x + pts[1][2] * c.y + pts[2][2] * c.z;
-            let w = pts[0][3] * c.x + pts[1][3] * c.y + pts[2][3] * c.z;
-            let frag_depth = z / w;
-            if c.x < 0.0 || c.y < 0.0 || c.z < 0.0 || zbuffer[(x + y * image.get_width()) as usize] > frag_depth { 
-                continue 
-            }
-            let discard = shader.fragment(c, &mut color);
-            if !discard {
-                zbuffer[(x + y * image.get_width()) as usize] = frag_depth;
-                image.set(x, y, color);
-            }
-        } 
-    }
-}
-
 #[allow(dead_code)]
 pub struct Camera {
     pub modelview: Mat4,
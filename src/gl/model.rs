@@ -1,4 +1,5 @@
 // use super::std;
+use std::collections::HashMap;
 use std::fs::File;
 use std::io::prelude::*;
 use std::path::Path;
@@ -12,9 +13,81 @@ pub struct Model {
     faces_: Vec<Vec<Vec3i>>, // this Vec3i means vertex/uv/normal
     norms_: Vec<Vec3f>,
     uv_: Vec<Vec2f>,
-    diffusemap_: TGAImage,
-    normalmap_: TGAImage,
-    specularmap_: TGAImage,
+    tangents_: Vec<Vec<Vec4f>>, // per vertex of each face, like faces_
+    textures_: HashMap<TextureSlot, TGAImage>,
+}
+
+/// What a texture of a `Model` is used for. `Model::open_with_texture` looks
+/// for each of the standard slots in a file next to the model, named after
+/// the model and `TextureSlot::suffix`.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum TextureSlot {
+    /// colour of the surface
+    Diffuse,
+    /// normals in model space
+    Normal,
+    /// normals in the tangent space of `Model::tangent`
+    NormalTangent,
+    /// exponent of the highlights
+    Specular,
+    /// strength of the highlights, from 0 to 1
+    Gloss,
+    /// light emitted by the surface
+    Glow,
+    /// ambient occlusion, as `OcclusionBaker` bakes it
+    Occlusion,
+    /// metalness in the blue channel and roughness in the green one, as in
+    /// glTF
+    MetalRough,
+    /// any other texture, in `_<name>.tga`
+    Custom(String),
+}
+
+impl TextureSlot {
+    /// The slots `Model::open_with_texture` loads.
+    pub fn standard() -> Vec<TextureSlot> {
+        vec![TextureSlot::Diffuse, TextureSlot::Normal, TextureSlot::NormalTangent, TextureSlot::Specular,
+             TextureSlot::Gloss, TextureSlot::Glow, TextureSlot::Occlusion, TextureSlot::MetalRough]
+    }
+    /// The slot called `name`, or after the file suffix without `_` and
+    /// `.tga`; `emissive` is the glow. Any other name is a custom slot.
+    pub fn from_name(name: &str) -> TextureSlot {
+        match name {
+            "diffuse" => TextureSlot::Diffuse,
+            "normal" | "nm" => TextureSlot::Normal,
+            "normal_tangent" | "nm_tangent" => TextureSlot::NormalTangent,
+            "specular" | "spec" => TextureSlot::Specular,
+            "gloss" => TextureSlot::Gloss,
+            "glow" | "emissive" => TextureSlot::Glow,
+            "occlusion" | "ao" => TextureSlot::Occlusion,
+            "metalrough" => TextureSlot::MetalRough,
+            _ => TextureSlot::Custom(name.to_string()),
+        }
+    }
+    pub fn name(&self) -> &str {
+        match *self {
+            TextureSlot::Diffuse => "diffuse",
+            TextureSlot::Normal => "normal",
+            TextureSlot::NormalTangent => "normal_tangent",
+            TextureSlot::Specular => "specular",
+            TextureSlot::Gloss => "gloss",
+            TextureSlot::Glow => "glow",
+            TextureSlot::Occlusion => "occlusion",
+            TextureSlot::MetalRough => "metalrough",
+            TextureSlot::Custom(ref name) => name,
+        }
+    }
+    /// End of the name of the file the slot is loaded from.
+    pub fn suffix(&self) -> String {
+        let stem = match *self {
+            TextureSlot::Normal => "nm",
+            TextureSlot::NormalTangent => "nm_tangent",
+            TextureSlot::Specular => "spec",
+            TextureSlot::Occlusion => "ao",
+            ref slot => slot.name(),
+        };
+        format!("_{}.tga", stem)
+    }
 }
 
 fn solver(x: &&str, faces: &mut Vec<Vec<Vec3i>>, verts: &mut Vec<Vec3f>, norms: &mut Vec<Vec3f>, uv: &mut Vec<Vec2f>) {
@@ -55,6 +128,11 @@ fn solver(x: &&str, faces: &mut Vec<Vec<Vec3i>>, verts: &mut Vec<Vec3f>, norms:
     }
 }
 
+/// `filename` with its extension replaced by `suffix`.
+fn texture_name(filename: &str, suffix: &str) -> String {
+    filename.split('.').next().unwrap().to_string() + suffix
+}
+
 impl Model {
     #[allow(dead_code)]
     pub fn open(filename: &str) -> Model {
@@ -81,37 +159,75 @@ impl Model {
             .map(|x| solver(x, &mut faces_vec, &mut verts_vec, &mut norm_vec, &mut uv_vec))
             .collect();
 
-        Model {
+        let mut ret = Model {
             verts_: verts_vec,
             faces_: faces_vec,
             norms_: norm_vec,
             uv_: uv_vec,
-            diffusemap_: TGAImage::new(),
-            normalmap_: TGAImage::new(),
-            specularmap_: TGAImage::new(),
-        }
+            tangents_: vec![],
+            textures_: HashMap::new(),
+        };
+        ret.tangents_ = ret.generate_tangents();
+        ret
     }
+    /// The model of `filename` with every standard texture found next to it.
     #[allow(dead_code)]
     pub fn open_with_texture(filename: &str) -> Model {
         let mut ret = Model::open(filename);
-        ret.load_texture(filename, "_diffuse.tga");
-        ret.load_texture(filename, "_nm.tga");
-        ret.load_texture(filename, "_spec.tga");
+        for slot in TextureSlot::standard() {
+            ret.load_texture(filename, slot);
+        }
         ret
     }
+    /// Load the texture of `slot` of the model of `filename`, if there is
+    /// one, and tell whether there was.
     #[allow(dead_code)]
-    fn load_texture(&mut self, filename: &str, suffix: &str) {
-        let prefix = filename.split('.').next().unwrap();
-        let texname = prefix.to_string() + suffix;
+    pub fn load_texture(&mut self, filename: &str, slot: TextureSlot) -> bool {
+        let texname = texture_name(filename, &slot.suffix());
+        if !Path::new(&texname).exists() {
+            return false;
+        }
         println!("{}", texname);
-        let mut tmp = match suffix {
-            "_diffuse.tga" => &mut self.diffusemap_,
-            "_nm.tga" => &mut self.normalmap_,
-            "_spec.tga" => &mut self.specularmap_,
-            _ => return,   
-        };
-        tmp.read_tga_file(&texname);
-        tmp.flip_vertically().unwrap();
+        let mut texture = TGAImage::new();
+        texture.read_tga_file(&texname);
+        texture.flip_vertically().unwrap();
+        self.textures_.insert(slot, texture);
+        true
+    }
+    /// Use `texture`, laid out like the loaded ones with its first row at
+    /// `v = 0`, for `slot`, in place of any it had.
+    #[allow(dead_code)]
+    pub fn attach_texture(&mut self, slot: TextureSlot, texture: TGAImage) {
+        self.textures_.insert(slot, texture);
+    }
+    #[allow(dead_code)]
+    pub fn detach_texture(&mut self, slot: &TextureSlot) -> Option<TGAImage> {
+        self.textures_.remove(slot)
+    }
+    #[allow(dead_code)]
+    pub fn has_texture(&self, slot: &TextureSlot) -> bool {
+        self.textures_.contains_key(slot)
+    }
+    #[allow(dead_code)]
+    pub fn texture(&self, slot: &TextureSlot) -> Option<&TGAImage> {
+        self.textures_.get(slot)
+    }
+    /// The slots holding a texture, in no particular order.
+    #[allow(dead_code)]
+    pub fn texture_slots(&self) -> Vec<&TextureSlot> {
+        self.textures_.keys().collect()
+    }
+    /// The texel of `slot` at `uvf`; `None` when the slot is empty.
+    #[allow(dead_code)]
+    pub fn sample(&self, slot: &TextureSlot, uvf: Vec2f) -> Option<Color> {
+        self.texture(slot).map(|texture| {
+            let uv = Vec2i::new(uvf[0] * texture.get_width() as f32, uvf[1] * texture.get_height() as f32);
+            texture.get(uv.x, uv.y)
+        })
+    }
+    /// `sample`, transparent black when the slot is empty.
+    fn texel(&self, slot: &TextureSlot, uvf: Vec2f) -> Color {
+        self.sample(slot, uvf).unwrap_or(Color::with_color(RGBAColor(0, 0, 0, 0)))
     }
     #[allow(dead_code)]
     pub fn nverts(&self) -> usize {
@@ -136,34 +252,150 @@ impl Model {
         ret
     }
     #[allow(dead_code)]
+    pub fn has_diffuse_map(&self) -> bool {
+        self.has_texture(&TextureSlot::Diffuse)
+    }
+    #[allow(dead_code)]
     pub fn diffuse(&self, uvf: Vec2f) -> Color {
-        let uv = Vec2i::new(uvf[0] * self.diffusemap_.get_width() as f32, uvf[1] * self.diffusemap_.get_height() as f32);
-        self.diffusemap_.get(uv.x, uv.y)
+        self.texel(&TextureSlot::Diffuse, uvf)
     }
     #[allow(dead_code)]
     pub fn uv(&self, iface: usize, nthvert: usize) -> Vec2f {
         self.uv_[self.faces_[iface][nthvert][1] as usize]
     }
     #[allow(dead_code)]
+    pub fn has_normal_map(&self) -> bool {
+        self.has_texture(&TextureSlot::Normal)
+    }
+    #[allow(dead_code)]
     pub fn normal(&self, uvf: Vec2f) -> Vec3f {
-        let uv = Vec2i::new(uvf[0] * self.normalmap_.get_width() as f32, uvf[1] * self.normalmap_.get_height() as f32);
-        let color = self.normalmap_.get(uv[0], uv[1]);
-        let mut res = Vec3f::zero();
-        for i in 0..3 {
-            res[2 - i] = color[i] as f32 / 255.0 * 2.0 - 1.0;
-        }
-        res
+        decode_normal(self.texel(&TextureSlot::Normal, uvf))
+    }
+    #[allow(dead_code)]
+    pub fn has_tangent_normal_map(&self) -> bool {
+        self.has_texture(&TextureSlot::NormalTangent)
+    }
+    /// Normal at `uvf` in the basis of the tangent, the bitangent and the
+    /// normal of the surface, see `Model::tangent`.
+    #[allow(dead_code)]
+    pub fn normal_tangent(&self, uvf: Vec2f) -> Vec3f {
+        decode_normal(self.texel(&TextureSlot::NormalTangent, uvf))
+    }
+    /// Ambient light reaching the surface at `uvf`, from 0 to 1; 1 without
+    /// an occlusion map.
+    pub fn occlusion(&self, uvf: Vec2f) -> f32 {
+        self.sample(&TextureSlot::Occlusion, uvf).map_or(1.0, |c| c[0] as f32 / 255.0)
+    }
+    #[allow(dead_code)]
+    pub fn has_metal_rough_map(&self) -> bool {
+        self.has_texture(&TextureSlot::MetalRough)
+    }
+    /// Metalness and roughness at `uvf`, from 0 to 1; `None` without a
+    /// metallic-roughness map.
+    pub fn metal_rough(&self, uvf: Vec2f) -> Option<(f32, f32)> {
+        self.sample(&TextureSlot::MetalRough, uvf).map(|c| (c[0] as f32 / 255.0, c[1] as f32 / 255.0))
     }
     pub fn specular(&self, uvf: Vec2f) -> f32 {
-        let uv = Vec2i::new(uvf[0] * self.specularmap_.get_width() as f32, uvf[1] * self.specularmap_.get_height() as f32);
-        self.specularmap_.get(uv.x, uv.y)[0] as f32 / 1.0
+        self.texel(&TextureSlot::Specular, uvf)[0] as f32 / 1.0
+    }
+    /// Strength of the highlights at `uvf`, from 0 to 1; 1 without a gloss
+    /// map.
+    #[allow(dead_code)]
+    pub fn gloss(&self, uvf: Vec2f) -> f32 {
+        self.sample(&TextureSlot::Gloss, uvf).map_or(1.0, |c| c[0] as f32 / 255.0)
+    }
+    /// Light emitted at `uvf`; black without a glow map.
+    #[allow(dead_code)]
+    pub fn glow(&self, uvf: Vec2f) -> Color {
+        self.sample(&TextureSlot::Glow, uvf).unwrap_or(Color::with_color(RGBColor(0, 0, 0)))
     }
 
-    pub fn face_normal(&mut self, iface: i32, nthvert: i32) -> Vec3f {
+    pub fn face_normal(&self, iface: i32, nthvert: i32) -> Vec3f {
         let idx = self.faces_[iface as usize][nthvert as usize][2] as usize;
-        self.norms_[idx] = self.norms_[idx].normalize();
-        return self.norms_[idx];
+        self.norms_[idx].normalize()
+    }
+    /// Unit tangent of a vertex, along which `u` grows, orthogonal to its
+    /// normal. `w` is the handedness: the bitangent, along which `v` grows,
+    /// is `w * cross(normal, tangent)`. All zero on faces without texture
+    /// coordinates or normals.
+    #[allow(dead_code)]
+    pub fn tangent(&self, iface: i32, nthvert: i32) -> Vec4f {
+        self.tangents_[iface as usize][nthvert as usize]
+    }
+    #[allow(dead_code)]
+    pub fn bitangent(&self, iface: i32, nthvert: i32) -> Vec3f {
+        let t = self.tangent(iface, nthvert);
+        cross(self.face_normal(iface, nthvert), t.proj3()) * t[3]
+    }
+
+    /// Tangents from the derivatives of the texture coordinates over every
+    /// triangle, summed over the triangles sharing a vertex, weighted by
+    /// their angle at it, then made orthogonal to its normal, as MikkTSpace
+    /// does. The vertices of triangles mirrored in texture space are kept
+    /// apart from the others, so that the seams of mirrored maps stay sharp.
+    fn generate_tangents(&self) -> Vec<Vec<Vec4f>> {
+        let valid = |face: &Vec<Vec3i>| face.len() == 3 && face.iter().all(|v| {
+            v[1] >= 0 && v[2] >= 0 && (v[1] as usize) < self.uv_.len() && (v[2] as usize) < self.norms_.len()
+        });
+        // vertex, texture coordinates, normal and handedness to the sums of
+        // the tangents and of the bitangents
+        let mut sums: HashMap<(i32, i32, i32, bool), (Vec3f, Vec3f)> = HashMap::new();
+        let mut mirrored = vec![false; self.faces_.len()];
+        for (iface, face) in self.faces_.iter().enumerate() {
+            if !valid(face) {
+                continue;
+            }
+            let p: Vec<Vec3f> = face.iter().map(|v| self.verts_[v[0] as usize]).collect();
+            let uv: Vec<Vec2f> = face.iter().map(|v| self.uv_[v[1] as usize]).collect();
+            let (e1, e2) = (p[1] - p[0], p[2] - p[0]);
+            let (d1, d2) = (uv[1] - uv[0], uv[2] - uv[0]);
+            let det = d1.x * d2.y - d2.x * d1.y;
+            if det.abs() < 1.0e-12 {
+                continue;
+            }
+            let t = (e1 * d2.y - e2 * d1.y) * (1.0 / det);
+            let b = (e2 * d1.x - e1 * d2.x) * (1.0 / det);
+            mirrored[iface] = det < 0.0;
+            for i in 0..3 {
+                let (a, c) = ((p[(i + 1) % 3] - p[i]).normalize(), (p[(i + 2) % 3] - p[i]).normalize());
+                let angle = (a * c).max(-1.0).min(1.0).acos();
+                let v = face[i];
+                let sum = sums.entry((v[0], v[1], v[2], mirrored[iface])).or_insert((Vec3f::zero(), Vec3f::zero()));
+                sum.0 = sum.0 + t * angle;
+                sum.1 = sum.1 + b * angle;
+            }
+        }
+        self.faces_.iter().enumerate().map(|(iface, face)| {
+            face.iter().map(|v| {
+                if !valid(face) {
+                    return Vec4f::zero();
+                }
+                let n = self.norms_[v[2] as usize].normalize();
+                let (t, b) = match sums.get(&(v[0], v[1], v[2], mirrored[iface])) {
+                    Some(&sum) => sum,
+                    None => (Vec3f::zero(), Vec3f::zero()),
+                };
+                let mut t = t - n * (n * t);
+                if t.norm() < 1.0e-12 {
+                    // no texture gradient here: any direction in the plane
+                    let other = if n.x.abs() < 0.9 { Vec3f::new(1.0, 0.0, 0.0) } else { Vec3f::new(0.0, 1.0, 0.0) };
+                    t = other - n * (n * other);
+                }
+                let t = t.normalize();
+                t.embed(if cross(n, t) * b < 0.0 { -1.0 } else { 1.0 })
+            }).collect()
+        }).collect()
+    }
+}
+
+/// The normal stored in the texel `color` of a normal map, red, green and
+/// blue giving `x`, `y` and `z`.
+fn decode_normal(color: Color) -> Vec3f {
+    let mut res = Vec3f::zero();
+    for i in 0..3 {
+        res[2 - i] = color[i] as f32 / 255.0 * 2.0 - 1.0;
     }
+    res
 }
 
 // pub fn nverts(&self) -> i32;
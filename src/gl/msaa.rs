@@ -0,0 +1,144 @@
+use gl::*;
+
+/// Sample positions relative to the pixel centre, in 1/16 of a pixel. These
+/// are the standard Direct3D patterns.
+const PATTERN_1X: [[i32; 2]; 1] = [[0, 0]];
+const PATTERN_2X: [[i32; 2]; 2] = [[4, 4], [-4, -4]];
+const PATTERN_4X: [[i32; 2]; 4] = [[-2, -6], [6, -2], [-6, 2], [2, 6]];
+const PATTERN_8X: [[i32; 2]; 8] = [[1, -3], [-1, 3], [5, 1], [-3, -5], [-5, 5], [-7, -1], [3, 7], [7, -7]];
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum SampleCount {
+    X1,
+    X2,
+    X4,
+    X8,
+}
+
+impl SampleCount {
+    pub fn count(&self) -> usize {
+        self.pattern().len()
+    }
+    /// Sample offsets from the pixel centre, in 1/16 of a pixel.
+    pub fn pattern(&self) -> &'static [[i32; 2]] {
+        match *self {
+            SampleCount::X1 => &PATTERN_1X,
+            SampleCount::X2 => &PATTERN_2X,
+            SampleCount::X4 => &PATTERN_4X,
+            SampleCount::X8 => &PATTERN_8X,
+        }
+    }
+}
+
+/// How the samples are filtered down to one colour per pixel.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ResolveFilter {
+    /// Average of the pixel's own samples.
+    Box,
+    /// Samples of the pixel and its neighbours, weighted by a one pixel wide
+    /// tent around the pixel centre. Softer than `Box`.
+    Tent,
+}
+
+/// Colour, depth and stencil storage with several samples per pixel. The triangles
+/// are rasterized into it as the `Target` of `Pipeline::triangle` and the result is
+/// filtered into an ordinary image with `resolve`.
+#[derive(Debug, Clone)]
+pub struct MsaaBuffer {
+    width: i32,
+    height: i32,
+    bytespp: i32,
+    samples: SampleCount,
+    color: Vec<u8>,
+    depth: Vec<f32>,
+    stencil: Vec<u8>,
+}
+
+impl MsaaBuffer {
+    #[allow(dead_code)]
+    pub fn new(width: i32, height: i32, bytespp: isize, samples: SampleCount) -> MsaaBuffer {
+        let n = (width * height) as usize * samples.count();
+        MsaaBuffer {
+            width: width,
+            height: height,
+            bytespp: bytespp as i32,
+            samples: samples,
+            color: vec![0; n * bytespp as usize],
+            depth: vec![std::f32::MIN; n],
+            stencil: vec![0; n],
+        }
+    }
+    #[allow(dead_code)]
+    pub fn get_width(&self) -> i32 { self.width }
+    #[allow(dead_code)]
+    pub fn get_height(&self) -> i32 { self.height }
+    #[allow(dead_code)]
+    pub fn get_bytespp(&self) -> i32 { self.bytespp }
+    #[allow(dead_code)]
+    pub fn samples(&self) -> SampleCount { self.samples }
+    /// Per-sample depth, `samples().count()` values per pixel, row by row.
+    #[allow(dead_code)]
+    pub fn depth(&self) -> &[f32] { &self.depth }
+    /// Per-sample stencil, laid out like the depth.
+    #[allow(dead_code)]
+    pub fn stencil(&self) -> &[u8] { &self.stencil }
+    pub fn buffers(&mut self) -> (&mut [u8], &mut [f32], &mut [u8]) {
+        (&mut self.color, &mut self.depth, &mut self.stencil)
+    }
+
+    fn sample(&self, x: i32, y: i32, s: usize, channel: usize) -> f32 {
+        let idx = ((x + y * self.width) as usize * self.samples.count() + s) * self.bytespp as usize;
+        self.color[idx + channel] as f32
+    }
+
+    /// Filter the samples into `image`, which must have the buffer's size and
+    /// number of bytes per pixel.
+    #[allow(dead_code)]
+    pub fn resolve(&self, image: &mut TGAImage, filter: ResolveFilter) {
+        assert!(image.get_width() == self.width && image.get_height() == self.height && image.get_bytespp() == self.bytespp,
+                "Error: MsaaBuffer::resolve image does not match the buffer.");
+        let n = self.samples.count();
+        let nbytes = self.bytespp as usize;
+        let pattern = self.samples.pattern();
+        let mut color = Color::new();
+        color.set_val(0, self.bytespp);
+        for y in 0..self.height {
+            for x in 0..self.width {
+                let mut sum = [0f32; 4];
+                let mut weight = 0f32;
+                match filter {
+                    ResolveFilter::Box => {
+                        for s in 0..n {
+                            for c in 0..nbytes {
+                                sum[c] += self.sample(x, y, s, c);
+                            }
+                        }
+                        weight = n as f32;
+                    }
+                    ResolveFilter::Tent => {
+                        for ny in (y - 1).max(0)..(y + 2).min(self.height) {
+                            for nx in (x - 1).max(0)..(x + 2).min(self.width) {
+                                for s in 0..n {
+                                    let dx = (nx - x) as f32 + pattern[s][0] as f32 / 16.0;
+                                    let dy = (ny - y) as f32 + pattern[s][1] as f32 / 16.0;
+                                    let w = (1.0 - dx.abs()).max(0.0) * (1.0 - dy.abs()).max(0.0);
+                                    if w <= 0.0 {
+                                        continue;
+                                    }
+                                    for c in 0..nbytes {
+                                        sum[c] += w * self.sample(nx, ny, s, c);
+                                    }
+                                    weight += w;
+                                }
+                            }
+                        }
+                    }
+                }
+                for c in 0..nbytes {
+                    color[c] = (sum[c] / weight + 0.5).min(255.0) as u8;
+                }
+                image.set(x, y, color);
+            }
+        }
+    }
+}
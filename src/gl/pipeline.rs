@@ -0,0 +1,1218 @@
+use gl::*;
+
+/// Side of the square screen tiles the parallel rasterizer bins into.
+pub const TILE_SIZE: i32 = 32;
+/// How far, in pixels, triangles may overhang the screen before they get
+/// clipped on the sides. Window coordinates up to this size keep the fixed
+/// point edge functions well within `i64`.
+pub const GUARD_BAND: f32 = 4096.0;
+/// Side of the tiles of the hierarchical depth buffer.
+pub const HIZ_TILE: i32 = 8;
+/// Vertices are snapped to 1/256 of a pixel before rasterization.
+pub const SUBPIXEL_BITS: i64 = 8;
+const SUBPIXEL_ONE: i64 = 1 << SUBPIXEL_BITS;
+const SUBPIXEL_HALF: i64 = SUBPIXEL_ONE / 2;
+
+/// Fixed-function state shared by the draw calls of a pass.
+#[allow(dead_code)]
+pub struct Pipeline {
+    pub frustum: Frustum,
+    /// Worker threads used by `draw`; 1 rasterizes on the calling thread.
+    pub threads: usize,
+    pub cull: CullMode,
+    /// Winding of the front faces once projected on the screen (y up).
+    pub front_face: Winding,
+    pub depth: DepthState,
+    pub stencil: StencilState,
+    pub blend: BlendState,
+    pub polygon_mode: PolygonMode,
+    /// Side of the square drawn for points, in pixels.
+    pub point_size: f32,
+    /// Offset added to the window z of the fragments of the next draws.
+    pub depth_bias: DepthBias,
+    /// Only the pixels inside this rectangle are drawn.
+    pub scissor: Option<Rect>,
+    /// Let `draw` skip the tiles of a triangle that are entirely behind
+    /// what the depth buffer holds.
+    pub hierarchical_z: bool,
+    width: i32,
+    height: i32,
+    coverage: Option<Vec<u32>>,
+    stats: RenderStats,
+    /// window z of the far end of the frustum
+    max_depth: f32,
+}
+
+/// How `Pipeline` draws the triangles it is given: filled, as their three
+/// edges or as their three vertices.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum PolygonMode {
+    Fill,
+    Line,
+    Point,
+}
+
+/// Which faces `Pipeline` drops before rasterization.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum CullMode {
+    None,
+    Back,
+    Front,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Winding {
+    /// counter-clockwise
+    Ccw,
+    /// clockwise
+    Cw,
+}
+
+/// Counters accumulated by the draw calls since the last `reset_stats`.
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub struct RenderStats {
+    /// triangles submitted
+    pub triangles: usize,
+    /// triangles dropped by face culling
+    pub culled: usize,
+    /// triangles that had to be clipped, the others fit in the guard band
+    pub clipped: usize,
+    /// `HIZ_TILE` sized tiles of triangles skipped by the hierarchical depth
+    /// test
+    pub hiz_rejected: usize,
+}
+
+/// A half-open pixel rectangle, `x0..x1` by `y0..y1`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Rect {
+    pub x0: i32,
+    pub y0: i32,
+    pub x1: i32,
+    pub y1: i32,
+}
+
+impl Rect {
+    pub fn new(x0: i32, y0: i32, x1: i32, y1: i32) -> Rect {
+        Rect { x0: x0, y0: y0, x1: x1, y1: y1 }
+    }
+    pub fn intersect(&self, other: &Rect) -> Rect {
+        Rect::new(self.x0.max(other.x0), self.y0.max(other.y0), self.x1.min(other.x1), self.y1.min(other.y1))
+    }
+    pub fn is_empty(&self) -> bool {
+        self.x0 >= self.x1 || self.y0 >= self.y1
+    }
+}
+
+/// A clipped triangle in screen space, ready for rasterization.
+#[derive(Debug, Clone, Copy)]
+struct Prim {
+    pts: [Vec4f; 3],
+    /// weights of each vertex relative to the triangle the shader saw
+    bar: [Vec3f; 3],
+    /// vertices in sub-pixel fixed point, counter-clockwise
+    fixed: [[i64; 2]; 3],
+    /// twice the area of the triangle in fixed point
+    area: i64,
+    /// winding of the vertices as submitted
+    winding: Winding,
+    /// 1 for the edges that own the samples lying exactly on them
+    bias: [i64; 3],
+    bbox: Rect,
+    /// largest change of window z from one pixel to the next
+    slope: f32,
+    /// depth bias, from `slope` and `Pipeline::depth_bias`
+    offset: f32,
+}
+
+impl Prim {
+    /// The triangle `pts` with its clip space weights `bar`, or `None` when it
+    /// does not cover any area once snapped to the sub-pixel grid.
+    fn new(mut pts: [Vec4f; 3], mut bar: [Vec3f; 3]) -> Option<Prim> {
+        let mut fixed = [[0i64; 2]; 3];
+        for i in 0..3 {
+            for j in 0..2 {
+                fixed[i][j] = (pts[i][j] / pts[i][3] * SUBPIXEL_ONE as f32).round() as i64;
+            }
+        }
+        let mut area = edge(fixed[0], fixed[1], fixed[2]);
+        if area == 0 {
+            return None;
+        }
+        let winding = if area > 0 { Winding::Ccw } else { Winding::Cw };
+        if area < 0 {
+            pts.swap(1, 2);
+            bar.swap(1, 2);
+            fixed.swap(1, 2);
+            area = -area;
+        }
+        // Top-left rule: a sample on an edge shared by two triangles belongs
+        // to the one for which the edge is a left edge (going down) or a top
+        // edge (horizontal, going left). Screen y goes up.
+        let mut bias = [0i64; 3];
+        for i in 0..3 {
+            let a = fixed[(i + 1) % 3];
+            let b = fixed[(i + 2) % 3];
+            let (dx, dy) = (b[0] - a[0], b[1] - a[1]);
+            bias[i] = if dy < 0 || (dy == 0 && dx < 0) { 1 } else { 0 };
+        }
+        let (mut lo, mut hi) = ([std::i64::MAX; 2], [std::i64::MIN; 2]);
+        for v in fixed.iter() {
+            for j in 0..2 {
+                lo[j] = lo[j].min(v[j]);
+                hi[j] = hi[j].max(v[j]);
+            }
+        }
+        // pixels whose centre lies within the vertices' extent
+        let first = |v: i64| (v - SUBPIXEL_HALF + SUBPIXEL_ONE - 1).div_euclid(SUBPIXEL_ONE) as i32;
+        // gradient of the plane z(x, y) through the vertices
+        let p: Vec<[f32; 3]> = pts.iter().map(|v| [v[0] / v[3], v[1] / v[3], v[2] / v[3]]).collect();
+        let (e1, e2) = ([p[1][0] - p[0][0], p[1][1] - p[0][1], p[1][2] - p[0][2]], [p[2][0] - p[0][0], p[2][1] - p[0][1], p[2][2] - p[0][2]]);
+        let det = e1[0] * e2[1] - e2[0] * e1[1];
+        let slope = if det != 0.0 {
+            ((e1[2] * e2[1] - e2[2] * e1[1]) / det).abs().max(((e2[2] * e1[0] - e1[2] * e2[0]) / det).abs())
+        } else {
+            0.0
+        };
+        let last = |v: i64| (v - SUBPIXEL_HALF).div_euclid(SUBPIXEL_ONE) as i32 + 1;
+        Some(Prim {
+            pts: pts,
+            bar: bar,
+            fixed: fixed,
+            area: area,
+            winding: winding,
+            bias: bias,
+            bbox: Rect::new(first(lo[0]), first(lo[1]), last(hi[0]), last(hi[1])),
+            slope: slope,
+            offset: 0.0,
+        })
+    }
+}
+
+impl Prim {
+    /// Pixels the primitive may cover. `bbox` only accounts for pixel
+    /// centres; samples lie within half a pixel of them.
+    fn footprint(&self, samples: &[[i32; 2]]) -> Rect {
+        let b = self.bbox;
+        if samples.len() > 1 {
+            Rect::new(b.x0 - 1, b.y0 - 1, b.x1 + 1, b.y1 + 1)
+        } else {
+            b
+        }
+    }
+}
+
+/// A clipped line in screen space. Triangles drawn with `PolygonMode::Line`
+/// become three of them, with `bar` still relative to the triangle.
+#[derive(Debug, Clone, Copy)]
+struct LinePrim {
+    pts: [Vec4f; 2],
+    bar: [Vec3f; 2],
+    /// whether the stencil front face settings apply
+    front: bool,
+    bbox: Rect,
+    offset: f32,
+}
+
+impl LinePrim {
+    fn new(pts: [Vec4f; 2], bar: [Vec3f; 2], front: bool, offset: f32) -> LinePrim {
+        let (a, b) = ([pts[0][0] / pts[0][3], pts[0][1] / pts[0][3]], [pts[1][0] / pts[1][3], pts[1][1] / pts[1][3]]);
+        LinePrim {
+            pts: pts,
+            bar: bar,
+            front: front,
+            offset: offset,
+            bbox: Rect::new(a[0].min(b[0]).floor() as i32, a[1].min(b[1]).floor() as i32,
+                            a[0].max(b[0]).floor() as i32 + 1, a[1].max(b[1]).floor() as i32 + 1),
+        }
+    }
+}
+
+/// A point in screen space, drawn as a square of `Pipeline::point_size`.
+#[derive(Debug, Clone, Copy)]
+struct PointPrim {
+    pos: Vec4f,
+    bar: Vec3f,
+    front: bool,
+    bbox: Rect,
+    offset: f32,
+}
+
+impl PointPrim {
+    fn new(pos: Vec4f, bar: Vec3f, front: bool, size: f32, offset: f32) -> PointPrim {
+        // pixels whose centre is inside the square
+        let (x, y, half) = (pos[0] / pos[3], pos[1] / pos[3], size / 2.0);
+        let first = |v: f32| (v - half - 0.5).ceil() as i32;
+        let last = |v: f32| (v + half - 0.5).ceil() as i32;
+        PointPrim {
+            pos: pos,
+            bar: bar,
+            front: front,
+            offset: offset,
+            bbox: Rect::new(first(x), first(y), last(x), last(y)),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+enum Primitive {
+    Triangle(Prim),
+    Line(LinePrim),
+    Point(PointPrim),
+}
+
+impl Primitive {
+    fn footprint(&self, samples: &[[i32; 2]]) -> Rect {
+        match *self {
+            Primitive::Triangle(ref prim) => prim.footprint(samples),
+            Primitive::Line(ref line) => line.bbox,
+            Primitive::Point(ref point) => point.bbox,
+        }
+    }
+    /// Depth of the fragment closest to the viewer, as stored in the buffer.
+    fn nearest(&self, state: &RasterState) -> f32 {
+        let (pts, offset): (&[Vec4f], f32) = match *self {
+            Primitive::Triangle(ref prim) => (&prim.pts, prim.offset),
+            Primitive::Line(ref line) => (&line.pts, line.offset),
+            Primitive::Point(ref point) => (std::slice::from_ref(&point.pos), point.offset),
+        };
+        let depths = pts.iter().map(|p| state.depth_value(p[2] / p[3], offset));
+        match hiz_keeps_min(state.depth.func) {
+            Some(false) => depths.fold(std::f32::MAX, f32::min),
+            _ => depths.fold(std::f32::MIN, f32::max),
+        }
+    }
+    fn raster<S: Shader>(&self, shader: &S, varyings: &[S::Varyings; 3], state: &RasterState, target: &mut Target, rect: Rect) {
+        let rect = match state.scissor {
+            Some(ref scissor) => rect.intersect(scissor),
+            None => rect,
+        };
+        if target.hiz.is_empty() {
+            return self.raster_rect(shader, varyings, state, target, rect);
+        }
+        let area = self.footprint(target.samples).intersect(&rect);
+        if area.is_empty() {
+            return;
+        }
+        let nearest = self.nearest(state);
+        for ty in (area.y0 / HIZ_TILE)..((area.y1 - 1) / HIZ_TILE + 1) {
+            for tx in (area.x0 / HIZ_TILE)..((area.x1 - 1) / HIZ_TILE + 1) {
+                if !target.hiz_visible(tx, ty, nearest, state.depth.func) {
+                    target.rejected += 1;
+                    continue;
+                }
+                let tile = Rect::new(tx * HIZ_TILE, ty * HIZ_TILE, (tx + 1) * HIZ_TILE, (ty + 1) * HIZ_TILE);
+                self.raster_rect(shader, varyings, state, target, area.intersect(&tile));
+                if state.depth.write {
+                    let cols = target.hiz_cols();
+                    target.hiz[tx as usize + (ty - target.y0 / HIZ_TILE) as usize * cols].dirty = true;
+                }
+            }
+        }
+    }
+    fn raster_rect<S: Shader>(&self, shader: &S, varyings: &[S::Varyings; 3], state: &RasterState, target: &mut Target, rect: Rect) {
+        match *self {
+            Primitive::Triangle(ref prim) => rasterize(prim, shader, varyings, state, target, rect),
+            Primitive::Line(ref line) => raster_line(line, shader, varyings, state, target, rect),
+            Primitive::Point(ref point) => raster_point(point, shader, varyings, state, target, rect),
+        }
+    }
+}
+
+/// Edge function: twice the signed area of `a`, `b`, `p`, positive when `p`
+/// lies to the left of `a` -> `b`.
+fn edge(a: [i64; 2], b: [i64; 2], p: [i64; 2]) -> i64 {
+    (b[0] - a[0]) * (p[1] - a[1]) - (b[1] - a[1]) * (p[0] - a[0])
+}
+
+/// Copy of the fixed-function state the rasterizer needs, shared by the
+/// workers.
+#[derive(Debug, Clone, Copy)]
+struct RasterState {
+    front_face: Winding,
+    depth: DepthState,
+    stencil: StencilState,
+    blend: BlendState,
+    scissor: Option<Rect>,
+    /// from window z to the depth range
+    depth_scale: f32,
+}
+
+impl RasterState {
+    /// Value tested against and stored in the depth buffer for window z `z`
+    /// of a primitive biased by `offset`.
+    fn depth_value(&self, z: f32, offset: f32) -> f32 {
+        self.depth.near + (z + offset) * self.depth_scale
+    }
+    /// Stencil settings for a front or back primitive, `None` when there is
+    /// no stencil test to run.
+    fn stencil_face(&self, front: bool, target: &Target) -> Option<StencilFace> {
+        if self.stencil.enabled && target.stencil.is_some() {
+            Some(*self.stencil.face(front))
+        } else {
+            None
+        }
+    }
+}
+
+/// What `Pipeline` draws into: colour buffers, a depth buffer and maybe a
+/// stencil buffer, all of the same size. It is made from an image and its
+/// depth buffer with `Target::new`, or from a `Framebuffer` or an
+/// `MsaaBuffer`, which convert into one.
+///
+/// Internally, a mutable view on a band of rows of those buffers, with
+/// `samples.len()` samples per pixel stored one after the other.
+pub struct Target<'a> {
+    colors: Vec<ColorBuffer<'a>>,
+    depth: &'a mut [f32],
+    stencil: Option<&'a mut [u8]>,
+    coverage: Option<&'a mut [u32]>,
+    width: i32,
+    /// sample offsets from the pixel centre, in 1/16 of a pixel
+    samples: &'static [[i32; 2]],
+    /// first row held by the buffers
+    y0: i32,
+    /// hierarchical depth of the rows held, empty when not in use
+    hiz: Vec<HizTile>,
+    /// tiles rejected with `hiz`
+    rejected: usize,
+}
+
+/// A colour buffer of a `Target`, with its number of channels per sample.
+enum ColorBuffer<'a> {
+    Bytes(&'a mut [u8], usize),
+    Floats(&'a mut [f32], usize),
+}
+
+impl<'a> ColorBuffer<'a> {
+    fn attachment(attachment: &'a mut Attachment) -> ColorBuffer<'a> {
+        let n = attachment.format().channels();
+        if attachment.bytes_mut().is_some() {
+            ColorBuffer::Bytes(attachment.bytes_mut().unwrap(), n)
+        } else {
+            ColorBuffer::Floats(attachment.floats_mut().unwrap(), n)
+        }
+    }
+    /// Split into pieces of `samples` samples.
+    fn chunks(self, samples: usize) -> std::vec::IntoIter<ColorBuffer<'a>> {
+        match self {
+            ColorBuffer::Bytes(b, n) => b.chunks_mut(samples * n).map(|c| ColorBuffer::Bytes(c, n)).collect::<Vec<_>>().into_iter(),
+            ColorBuffer::Floats(f, n) => f.chunks_mut(samples * n).map(|c| ColorBuffer::Floats(c, n)).collect::<Vec<_>>().into_iter(),
+        }
+    }
+    /// Write `color` to `sample`, blending it with what is there already. A
+    /// gray goes to every channel and a colour without alpha is opaque.
+    fn set(&mut self, sample: usize, color: Color, blend: &BlendState) {
+        let raw = color.raw();
+        let bgra = match color.nbytes() {
+            1 => [raw[0], raw[0], raw[0], 255],
+            4 => raw,
+            _ => [raw[0], raw[1], raw[2], 255],
+        };
+        match *self {
+            ColorBuffer::Bytes(ref mut bytes, n) if !blend.enabled => {
+                bytes[sample * n..(sample + 1) * n].copy_from_slice(&bgra[..n]);
+            }
+            _ => {
+                let src = [bgra[0] as f32 / 255.0, bgra[1] as f32 / 255.0, bgra[2] as f32 / 255.0, bgra[3] as f32 / 255.0];
+                self.set_value(sample, src, blend);
+            }
+        }
+    }
+    /// Same as `set` for a value already normalized. A single channel holds
+    /// the red, or the gray.
+    fn set_value(&mut self, sample: usize, src: [f32; 4], blend: &BlendState) {
+        match *self {
+            ColorBuffer::Bytes(ref mut bytes, n) => {
+                let pixel = &mut bytes[sample * n..(sample + 1) * n];
+                let out = if blend.enabled {
+                    let mut dst = [0f32, 0.0, 0.0, 1.0];
+                    for c in 0..n {
+                        dst[c] = pixel[c] as f32 / 255.0;
+                    }
+                    blend.apply(src, dst)
+                } else {
+                    src
+                };
+                for c in 0..n {
+                    pixel[c] = (out[c].max(0.0).min(1.0) * 255.0 + 0.5) as u8;
+                }
+            }
+            ColorBuffer::Floats(ref mut floats, n) => {
+                let pixel = &mut floats[sample * n..(sample + 1) * n];
+                let out = if blend.enabled {
+                    let dst = if n == 1 { [pixel[0], pixel[0], pixel[0], 1.0] } else { [pixel[0], pixel[1], pixel[2], pixel[3]] };
+                    blend.combine(src, dst)
+                } else {
+                    src
+                };
+                if n == 1 {
+                    pixel[0] = out[2];
+                } else {
+                    pixel.copy_from_slice(&out);
+                }
+            }
+        }
+    }
+}
+
+/// The farthest depth stored in a `HIZ_TILE` square: the smallest value for
+/// the `Greater` and `GEqual` tests, the largest for `Less` and `LEqual`.
+#[derive(Debug, Clone, Copy)]
+struct HizTile {
+    far: f32,
+    /// `far` has to be computed again before use
+    dirty: bool,
+}
+
+/// Whether the farthest depth is the smallest one, `None` for the tests
+/// the hierarchical depth buffer cannot help with.
+fn hiz_keeps_min(func: CompareFunc) -> Option<bool> {
+    match func {
+        CompareFunc::Greater | CompareFunc::GEqual => Some(true),
+        CompareFunc::Less | CompareFunc::LEqual => Some(false),
+        _ => None,
+    }
+}
+
+impl<'a> Target<'a> {
+    /// The image, with `zbuffer` holding a depth per pixel.
+    pub fn new(image: &'a mut TGAImage, zbuffer: &'a mut Vec<f32>) -> Target<'a> {
+        assert!(zbuffer.len() == (image.get_width() * image.get_height()) as usize,
+                "Error: the depth buffer does not match the {}x{} image.", image.get_width(), image.get_height());
+        let bytespp = image.get_bytespp() as usize;
+        Target {
+            width: image.get_width(),
+            samples: SampleCount::X1.pattern(),
+            y0: 0,
+            hiz: vec![],
+            rejected: 0,
+            colors: vec![ColorBuffer::Bytes(image.buffer(), bytespp)],
+            depth: zbuffer,
+            stencil: None,
+            coverage: None,
+        }
+    }
+    /// Add a stencil buffer of one byte per pixel.
+    #[allow(dead_code)]
+    pub fn with_stencil(mut self, stencil: &'a mut Vec<u8>) -> Target<'a> {
+        assert!(stencil.len() == self.depth.len(), "Error: the stencil buffer does not match the depth buffer.");
+        self.stencil = Some(stencil);
+        self
+    }
+    /// Coverage and depth are computed for every sample of `buffer`,
+    /// `Shader::fragment` runs once per pixel.
+    pub fn msaa(buffer: &'a mut MsaaBuffer) -> Target<'a> {
+        let width = buffer.get_width();
+        let bytespp = buffer.get_bytespp() as usize;
+        let samples = buffer.samples().pattern();
+        let (color, depth, stencil) = buffer.buffers();
+        Target {
+            colors: vec![ColorBuffer::Bytes(color, bytespp)],
+            depth: depth,
+            stencil: Some(stencil),
+            coverage: None,
+            width: width,
+            samples: samples,
+            y0: 0,
+            hiz: vec![],
+            rejected: 0,
+        }
+    }
+    /// The outputs of the fragments go to the colour attachments, see
+    /// `Shader::fragment_outputs`.
+    pub fn framebuffer(framebuffer: &'a mut Framebuffer) -> Target<'a> {
+        let width = framebuffer.get_width();
+        let (colors, depth, stencil) = framebuffer.buffers();
+        Target {
+            colors: colors.iter_mut().map(ColorBuffer::attachment).collect(),
+            depth: depth,
+            stencil: stencil,
+            coverage: None,
+            width: width,
+            samples: SampleCount::X1.pattern(),
+            y0: 0,
+            hiz: vec![],
+            rejected: 0,
+        }
+    }
+    /// Count the fragments of every pixel in `coverage`, which must hold a
+    /// count for each pixel of the target.
+    fn with_coverage(mut self, coverage: Option<&'a mut Vec<u32>>) -> Target<'a> {
+        if let Some(coverage) = coverage {
+            let height = self.depth.len() / (self.width as usize * self.samples.len());
+            assert!(coverage.len() == self.width as usize * height,
+                    "Error: the {}x{} render target does not match the size of the pipeline.", self.width, height);
+            self.coverage = Some(coverage);
+        }
+        self
+    }
+    /// Split the view into bands of `rows` rows.
+    fn bands(self, rows: i32) -> Vec<Target<'a>> {
+        let band = (rows * self.width) as usize;
+        let hiz_band = (rows / HIZ_TILE) as usize * self.hiz_cols();
+        let mut hiz = self.hiz.chunks(hiz_band.max(1));
+        let n = self.samples.len();
+        let mut coverage = self.coverage.map(|c| c.chunks_mut(band));
+        let mut stencil = self.stencil.map(|c| c.chunks_mut(band * n));
+        let mut colors: Vec<_> = self.colors.into_iter().map(|c| c.chunks(band * n)).collect();
+        let mut ret = vec![];
+        for (i, depth) in self.depth.chunks_mut(band * n).enumerate() {
+            ret.push(Target {
+                colors: colors.iter_mut().map(|c| c.next().unwrap()).collect(),
+                depth: depth,
+                stencil: stencil.as_mut().and_then(|c| c.next()),
+                coverage: coverage.as_mut().and_then(|c| c.next()),
+                width: self.width,
+                samples: self.samples,
+                y0: self.y0 + i as i32 * rows,
+                hiz: hiz.next().map_or(vec![], |h| h.to_vec()),
+                rejected: 0,
+            });
+        }
+        ret
+    }
+    fn hiz_cols(&self) -> usize {
+        ((self.width + HIZ_TILE - 1) / HIZ_TILE) as usize
+    }
+    /// Start a hierarchical depth buffer for the rows held, whose bands must
+    /// start on a tile boundary.
+    fn enable_hiz(&mut self) {
+        let rows = self.rect().y1 - self.y0;
+        self.hiz = vec![HizTile { far: 0.0, dirty: true }; self.hiz_cols() * ((rows + HIZ_TILE - 1) / HIZ_TILE) as usize];
+    }
+    /// Whether a fragment at depth `nearest` may pass the depth test
+    /// somewhere in the tile `tx`, `ty`.
+    fn hiz_visible(&mut self, tx: i32, ty: i32, nearest: f32, func: CompareFunc) -> bool {
+        let keep_min = match hiz_keeps_min(func) {
+            Some(keep_min) => keep_min,
+            None => return true,
+        };
+        let i = tx as usize + (ty - self.y0 / HIZ_TILE) as usize * self.hiz_cols();
+        if self.hiz[i].dirty {
+            let tile = self.rect().intersect(&Rect::new(tx * HIZ_TILE, ty * HIZ_TILE, (tx + 1) * HIZ_TILE, (ty + 1) * HIZ_TILE));
+            let n = self.samples.len();
+            let mut far = if keep_min { std::f32::MAX } else { std::f32::MIN };
+            for y in tile.y0..tile.y1 {
+                let row = self.index(tile.x0, y) * n;
+                for &z in self.depth[row..row + (tile.x1 - tile.x0) as usize * n].iter() {
+                    far = if keep_min { far.min(z) } else { far.max(z) };
+                }
+            }
+            self.hiz[i] = HizTile { far: far, dirty: false };
+        }
+        func.test(nearest, self.hiz[i].far)
+    }
+    fn rect(&self) -> Rect {
+        let rows = self.depth.len() / (self.width as usize * self.samples.len());
+        Rect::new(0, self.y0, self.width, self.y0 + rows as i32)
+    }
+    /// Index of the pixel `x`, `y`; its samples start at `index * samples.len()`.
+    fn index(&self, x: i32, y: i32) -> usize {
+        (x + (y - self.y0) * self.width) as usize
+    }
+    fn stencil_at(&self, sample: usize) -> u8 {
+        self.stencil.as_ref().map_or(0, |s| s[sample])
+    }
+    fn stencil_op(&mut self, sample: usize, state: &StencilState, op: StencilOp) {
+        if let Some(ref mut stencil) = self.stencil {
+            stencil[sample] = state.update(op, stencil[sample]);
+        }
+    }
+    /// Write the outputs of a fragment to `sample` of the colour buffers.
+    fn set(&mut self, sample: usize, out: &FragmentOutputs, blend: &BlendState) {
+        for (i, buffer) in self.colors.iter_mut().enumerate() {
+            let hdr = match *buffer {
+                ColorBuffer::Floats(..) => out.hdr(),
+                ColorBuffer::Bytes(..) => out.hdr().filter(|_| out.color().is_none()),
+            };
+            match (out.get(i).or(hdr), out.color()) {
+                (Some(value), _) => buffer.set_value(sample, value, blend),
+                (None, Some(color)) => buffer.set(sample, color, blend),
+                (None, None) => {}
+            }
+        }
+    }
+}
+
+impl<'a> From<&'a mut MsaaBuffer> for Target<'a> {
+    fn from(buffer: &'a mut MsaaBuffer) -> Target<'a> {
+        Target::msaa(buffer)
+    }
+}
+
+impl<'a> From<&'a mut Framebuffer> for Target<'a> {
+    fn from(framebuffer: &'a mut Framebuffer) -> Target<'a> {
+        Target::framebuffer(framebuffer)
+    }
+}
+
+impl Pipeline {
+    #[allow(dead_code)]
+    pub fn new(width: i32, height: i32, depth: f32) -> Pipeline {
+        Pipeline {
+            frustum: Frustum::with_guard_band(width, height, GUARD_BAND),
+            threads: 1,
+            cull: CullMode::None,
+            front_face: Winding::Ccw,
+            depth: DepthState::new(depth),
+            stencil: StencilState::new(),
+            blend: BlendState::new(),
+            polygon_mode: PolygonMode::Fill,
+            point_size: 1.0,
+            depth_bias: DepthBias::new(),
+            scissor: None,
+            hierarchical_z: true,
+            width: width,
+            height: height,
+            coverage: None,
+            stats: RenderStats::default(),
+            max_depth: depth,
+        }
+    }
+
+    /// Fill `zbuffer` with the clear value of the depth state.
+    #[allow(dead_code)]
+    pub fn clear_depth(&self, zbuffer: &mut [f32]) {
+        for z in zbuffer.iter_mut() {
+            *z = self.depth.clear;
+        }
+    }
+
+    fn state(&self) -> RasterState {
+        RasterState {
+            front_face: self.front_face,
+            depth: self.depth,
+            stencil: self.stencil,
+            blend: self.blend,
+            scissor: self.scissor,
+            depth_scale: (self.depth.far - self.depth.near) / self.max_depth,
+        }
+    }
+
+    #[allow(dead_code)]
+    pub fn stats(&self) -> RenderStats {
+        self.stats
+    }
+    #[allow(dead_code)]
+    pub fn reset_stats(&mut self) {
+        self.stats = RenderStats::default();
+    }
+
+    /// Count how many times every pixel gets rasterized, whatever the depth
+    /// test says. A closed mesh drawn without overlap covers each pixel once.
+    #[allow(dead_code)]
+    pub fn set_coverage_debug(&mut self, enabled: bool) {
+        self.coverage = if enabled { Some(vec![0; (self.width * self.height) as usize]) } else { None };
+    }
+    /// Per-pixel coverage counts since the debug mode was enabled.
+    #[allow(dead_code)]
+    pub fn coverage(&self) -> Option<&[u32]> {
+        self.coverage.as_ref().map(|c| &c[..])
+    }
+    /// The coverage counts as a grayscale image, brightest where the most
+    /// triangles overlap.
+    #[allow(dead_code)]
+    pub fn coverage_image(&self) -> Option<TGAImage> {
+        let coverage = match self.coverage {
+            Some(ref c) => c,
+            None => return None,
+        };
+        let max = coverage.iter().cloned().max().unwrap_or(0).max(1);
+        let mut image = TGAImage::with_info(self.width as isize, self.height as isize, tga_image::GRAYSCALE);
+        for (i, &n) in coverage.iter().enumerate() {
+            image.set(i as i32 % self.width, i as i32 / self.width, Color::grayscale((n * 255 / max) as u8));
+        }
+        Some(image)
+    }
+
+    /// Clip the triangle `pts` (as returned by `Shader::vertex`, with its
+    /// `varyings`) against the frustum and rasterize every piece that is left
+    /// into `target`.
+    pub fn triangle<'t, S: Shader, T: Into<Target<'t>>>(&mut self, pts: &[Vec4f], varyings: &[S::Varyings; 3], shader: &S, target: T) {
+        let prims = self.setup(pts, shader.interpolation());
+        self.raster(&prims, varyings, shader, target.into());
+    }
+
+    /// Clip and draw the line `pts[0]` - `pts[1]`, interpolating the
+    /// `varyings` of its two ends.
+    #[allow(dead_code)]
+    pub fn line<'t, S: Shader, T: Into<Target<'t>>>(&mut self, pts: &[Vec4f], varyings: &[S::Varyings; 2], shader: &S, target: T) {
+        let a = ClipVertex { pos: pts[0], bar: Vec3f::new(1, 0, 0) };
+        let b = ClipVertex { pos: pts[1], bar: Vec3f::new(0, 1, 0) };
+        let offset = self.depth_bias.offset(0.0);
+        let prims: Vec<Primitive> = self.setup_line(a, b, pts, shader.interpolation(), true, offset).into_iter().collect();
+        // the third vertex always has a weight of 0
+        let varyings = [varyings[0].clone(), varyings[1].clone(), varyings[1].clone()];
+        self.raster(&prims, &varyings, shader, target.into());
+    }
+
+    /// Draw the point `pt`, the shader gets its `varyings` as they are.
+    #[allow(dead_code)]
+    pub fn point<'t, S: Shader, T: Into<Target<'t>>>(&mut self, pt: Vec4f, varyings: &S::Varyings, shader: &S, target: T) {
+        let offset = self.depth_bias.offset(0.0);
+        let prims: Vec<Primitive> = self.setup_point(ClipVertex { pos: pt, bar: Vec3f::new(1, 0, 0) }, true, offset).into_iter().collect();
+        let varyings = [varyings.clone(), varyings.clone(), varyings.clone()];
+        self.raster(&prims, &varyings, shader, target.into());
+    }
+
+    fn raster<S: Shader>(&mut self, prims: &[Primitive], varyings: &[S::Varyings; 3], shader: &S, target: Target) {
+        let state = self.state();
+        let mut coverage = self.coverage.take();
+        {
+            let mut target = target.with_coverage(coverage.as_mut());
+            let rect = target.rect();
+            for prim in prims.iter() {
+                prim.raster(shader, varyings, &state, &mut target, rect);
+            }
+        }
+        self.coverage = coverage;
+    }
+
+    /// Shade and rasterize every face of `model` into `target`.
+    ///
+    /// With more than one thread, the vertex stage still runs here, one face
+    /// at a time. The varyings are kept per face and the triangles are binned
+    /// into screen tiles; the workers then share the shader and the varyings
+    /// to run the fragment stage, hence the `Sync` bound. Each worker owns
+    /// whole rows of tiles and walks their triangles in submission order, so
+    /// the result is the same as drawing the faces one after another.
+    pub fn draw<'t, S, T>(&mut self, camera: &Camera, model: &Model, shader: &S, target: T)
+        where S: Shader + Sync, T: Into<Target<'t>>
+    {
+        let mut coverage = self.coverage.take();
+        self.draw_into(camera, model, shader, target.into().with_coverage(coverage.as_mut()));
+        self.coverage = coverage;
+    }
+
+    /// Run `shader` over every pixel of `output`, with as many threads as
+    /// `draw`. There is no depth test and no blending, the pixels the shader
+    /// does not discard are replaced.
+    #[allow(dead_code)]
+    pub fn full_screen<S: ScreenShader + Sync>(&self, shader: &S, output: &mut Attachment) {
+        let (width, height) = (output.get_width(), output.get_height());
+        let threads = self.threads.max(1) as i32;
+        let rows = (height + threads - 1) / threads;
+        let band = |y0: i32| -> Vec<Option<[f32; 4]>> {
+            let mut ret = vec![];
+            for y in y0..(y0 + rows).min(height) {
+                for x in 0..width {
+                    let mut color = [0f32; 4];
+                    ret.push(if shader.fragment(x, y, &mut color) { None } else { Some(color) });
+                }
+            }
+            ret
+        };
+        let colors: Vec<Option<[f32; 4]>> = if threads == 1 {
+            band(0)
+        } else {
+            let band = &band;
+            std::thread::scope(|scope| {
+                let workers: Vec<_> = (0..threads).map(|i| scope.spawn(move || band(i * rows))).collect();
+                workers.into_iter().flat_map(|w| w.join().unwrap()).collect()
+            })
+        };
+        for (i, color) in colors.into_iter().enumerate() {
+            if let Some(color) = color {
+                output.set(i as i32 % width, i as i32 / width, color);
+            }
+        }
+    }
+
+    fn draw_into<S>(&mut self, camera: &Camera, model: &Model, shader: &S, mut target: Target)
+        where S: Shader + Sync
+    {
+        let mut pts = [Vec4f::zero(); 3];
+        let state = self.state();
+        // the stencil operations and the coverage counts need every fragment
+        if self.hierarchical_z && hiz_keeps_min(self.depth.func).is_some() && !self.stencil.enabled && target.coverage.is_none() {
+            target.enable_hiz();
+        }
+        if self.threads <= 1 {
+            let rect = target.rect();
+            for i in 0..model.nfaces() {
+                let varyings = self.vertices(camera, model, shader, i, &mut pts);
+                for prim in self.setup(&pts, shader.interpolation()).iter() {
+                    prim.raster(shader, &varyings, &state, &mut target, rect);
+                }
+            }
+            self.stats.hiz_rejected += target.rejected;
+            return;
+        }
+
+        let mut varyings = Vec::with_capacity(model.nfaces());
+        let mut prims = vec![];
+        for i in 0..model.nfaces() {
+            let face = self.vertices(camera, model, shader, i, &mut pts);
+            for prim in self.setup(&pts, shader.interpolation()) {
+                prims.push((prim, varyings.len()));
+            }
+            varyings.push(face);
+        }
+        self.stats.hiz_rejected += self.raster_tiled(&prims, &varyings, shader, &state, target);
+    }
+
+    /// Run the vertex stage on the face `iface`: its positions go to `pts`,
+    /// its varyings are returned.
+    fn vertices<S: Shader>(&self, camera: &Camera, model: &Model, shader: &S, iface: usize, pts: &mut [Vec4f; 3]) -> [S::Varyings; 3] {
+        let (p0, v0) = shader.vertex(camera, model, iface as i32, 0);
+        let (p1, v1) = shader.vertex(camera, model, iface as i32, 1);
+        let (p2, v2) = shader.vertex(camera, model, iface as i32, 2);
+        *pts = [p0, p1, p2];
+        [v0, v1, v2]
+    }
+
+    /// Clip, cull and snap a triangle. With a polygon mode other than
+    /// `Fill`, the triangle only decides whether it is culled and its edges
+    /// or vertices are clipped on their own.
+    fn setup(&mut self, pts: &[Vec4f], interpolation: Interpolation) -> Vec<Primitive> {
+        self.stats.triangles += 1;
+        let mut ret = vec![];
+        let mut culled = false;
+        let mut front = None;
+        if !pts.iter().all(|&p| self.frustum.contains(p)) {
+            self.stats.clipped += 1;
+        }
+        for tri in self.frustum.clip_triangle(pts) {
+            if tri.iter().any(|v| v.pos[3] <= 0.0) {
+                continue;
+            }
+            let mut bar = [Vec3f::zero(); 3];
+            for i in 0..3 {
+                bar[i] = varying_bar(tri[i].bar, pts, interpolation);
+            }
+            if let Some(mut prim) = Prim::new([tri[0].pos, tri[1].pos, tri[2].pos], bar) {
+                prim.offset = self.depth_bias.offset(prim.slope);
+                // the pieces of a clipped triangle all keep its winding
+                let is_front = prim.winding == self.front_face;
+                match self.cull {
+                    CullMode::Back if !is_front => culled = true,
+                    CullMode::Front if is_front => culled = true,
+                    _ => {
+                        front = Some((is_front, prim.offset));
+                        ret.push(Primitive::Triangle(prim));
+                    }
+                }
+            }
+        }
+        if culled {
+            self.stats.culled += 1;
+        }
+        let (front, offset) = match front {
+            Some(face) if self.polygon_mode != PolygonMode::Fill => face,
+            _ => return ret,
+        };
+        let verts = [
+            ClipVertex { pos: pts[0], bar: Vec3f::new(1, 0, 0) },
+            ClipVertex { pos: pts[1], bar: Vec3f::new(0, 1, 0) },
+            ClipVertex { pos: pts[2], bar: Vec3f::new(0, 0, 1) },
+        ];
+        let mut ret = vec![];
+        for i in 0..3 {
+            let prim = match self.polygon_mode {
+                PolygonMode::Line => self.setup_line(verts[i], verts[(i + 1) % 3], pts, interpolation, front, offset),
+                _ => self.setup_point(verts[i], front, offset),
+            };
+            ret.extend(prim);
+        }
+        ret
+    }
+
+    fn setup_line(&self, a: ClipVertex, b: ClipVertex, pts: &[Vec4f], interpolation: Interpolation, front: bool, offset: f32) -> Option<Primitive> {
+        let (a, b) = match self.frustum.clip_segment(a, b) {
+            Some((a, b)) if a.pos[3] > 0.0 && b.pos[3] > 0.0 => (a, b),
+            _ => return None,
+        };
+        let bar = [varying_bar(a.bar, pts, interpolation), varying_bar(b.bar, pts, interpolation)];
+        Some(Primitive::Line(LinePrim::new([a.pos, b.pos], bar, front, offset)))
+    }
+
+    /// Points keep the weights they came with: there is nothing to
+    /// interpolate.
+    fn setup_point(&self, v: ClipVertex, front: bool, offset: f32) -> Option<Primitive> {
+        if v.pos[3] <= 0.0 || !self.frustum.contains(v.pos) {
+            return None;
+        }
+        Some(Primitive::Point(PointPrim::new(v.pos, v.bar, front, self.point_size, offset)))
+    }
+
+    /// Returns the number of tiles rejected by the hierarchical depth test.
+    fn raster_tiled<S: Shader + Sync>(&self, prims: &[(Primitive, usize)], varyings: &[[S::Varyings; 3]], shader: &S, state: &RasterState, target: Target) -> usize {
+        let screen = target.rect();
+        let tiles_x = (screen.x1 + TILE_SIZE - 1) / TILE_SIZE;
+        let tiles_y = (screen.y1 + TILE_SIZE - 1) / TILE_SIZE;
+
+        let mut bins = vec![vec![]; (tiles_x * tiles_y) as usize];
+        for (i, &(ref prim, _)) in prims.iter().enumerate() {
+            let b = prim.footprint(target.samples).intersect(&screen);
+            if b.is_empty() {
+                continue;
+            }
+            for ty in (b.y0 / TILE_SIZE)..((b.y1 - 1) / TILE_SIZE + 1) {
+                for tx in (b.x0 / TILE_SIZE)..((b.x1 - 1) / TILE_SIZE + 1) {
+                    bins[(tx + ty * tiles_x) as usize].push(i);
+                }
+            }
+        }
+
+        let mut work: Vec<Vec<Target>> = (0..self.threads).map(|_| vec![]).collect();
+        for (ty, band) in target.bands(TILE_SIZE).into_iter().enumerate() {
+            work[ty % self.threads].push(band);
+        }
+
+        let bins = &bins;
+        std::thread::scope(|scope| {
+            let workers: Vec<_> = work.into_iter().map(|mut targets| {
+                scope.spawn(move || {
+                    for target in targets.iter_mut() {
+                        let ty = target.y0 / TILE_SIZE;
+                        let band = target.rect();
+                        for tx in 0..tiles_x {
+                            let tile = band.intersect(&Rect::new(tx * TILE_SIZE, band.y0, (tx + 1) * TILE_SIZE, band.y1));
+                            for &i in bins[(tx + ty * tiles_x) as usize].iter() {
+                                let (ref prim, face) = prims[i];
+                                prim.raster(shader, &varyings[face], state, target, tile);
+                            }
+                        }
+                    }
+                    targets.iter().map(|t| t.rejected).sum::<usize>()
+                })
+            }).collect();
+            workers.into_iter().map(|w| w.join().unwrap()).sum()
+        })
+    }
+}
+
+/// Weights of a clipped vertex as the rasterizer interpolates them.
+fn varying_bar(b: Vec3f, pts: &[Vec4f], interpolation: Interpolation) -> Vec3f {
+    match interpolation {
+        Interpolation::Perspective => b,
+        Interpolation::NoPerspective => screen_bar(b, pts),
+    }
+}
+
+/// Turn clip space weights relative to `pts` into screen space ones.
+fn screen_bar(b: Vec3f, pts: &[Vec4f]) -> Vec3f {
+    let w = b.x * pts[0][3] + b.y * pts[1][3] + b.z * pts[2][3];
+    Vec3f::new(b.x * pts[0][3] / w, b.y * pts[1][3] / w, b.z * pts[2][3] / w)
+}
+
+/// Rasterize the part of `prim` that falls inside `rect`. Coverage, stencil
+/// and depth are evaluated at every sample of the target, the shader once per
+/// pixel at its centre. With `Shader::early_z` the tests run first and the
+/// shader only for the pixels with a sample left; otherwise the shader runs
+/// for every covered pixel and a discarded fragment leaves the stencil
+/// alone too.
+fn rasterize<S: Shader>(prim: &Prim, shader: &S, varyings: &[S::Varyings; 3], state: &RasterState, target: &mut Target, rect: Rect) {
+    let area = prim.footprint(target.samples).intersect(&rect);
+    if area.is_empty() {
+        return;
+    }
+    let pts = &prim.pts;
+    let bar = &prim.bar;
+    let perspective = shader.interpolation() == Interpolation::Perspective;
+    let early = shader.early_z();
+    let nsamples = target.samples.len();
+    // edge functions at the centre of the first pixel and their steps; the
+    // bias turns the top-left rule into a plain sign test
+    let origin = [((area.x0 as i64) << SUBPIXEL_BITS) + SUBPIXEL_HALF, ((area.y0 as i64) << SUBPIXEL_BITS) + SUBPIXEL_HALF];
+    let mut row = [0i64; 3];
+    let mut step_x = [0i64; 3];
+    let mut step_y = [0i64; 3];
+    let mut offsets = [[0i64; 3]; 8];
+    for i in 0..3 {
+        let a = prim.fixed[(i + 1) % 3];
+        let b = prim.fixed[(i + 2) % 3];
+        row[i] = edge(a, b, origin) + prim.bias[i] - 1;
+        step_x[i] = (a[1] - b[1]) << SUBPIXEL_BITS;
+        step_y[i] = (b[0] - a[0]) << SUBPIXEL_BITS;
+        for s in 0..nsamples {
+            let d = target.samples[s];
+            offsets[s][i] = ((a[1] - b[1]) * d[0] as i64 + (b[0] - a[0]) * d[1] as i64) << (SUBPIXEL_BITS - 4);
+        }
+    }
+    let inv_area = 1.0 / prim.area as f64;
+    let bary = |e: [i64; 3], i: usize| ((e[i] + 1 - prim.bias[i]) as f64 * inv_area) as f32;
+
+    let stencil = state.stencil_face(prim.winding == state.front_face, target);
+
+    let mut passed = [None; 8];
+    for y in area.y0..area.y1 {
+        let mut e = row;
+        for x in area.x0..area.x1 {
+            let center = e;
+            for i in 0..3 {
+                e[i] += step_x[i];
+            }
+            let idx = target.index(x, y);
+            let mut covered = false;
+            let mut any_passed = false;
+            for s in 0..nsamples {
+                let es = [center[0] + offsets[s][0], center[1] + offsets[s][1], center[2] + offsets[s][2]];
+                passed[s] = None;
+                if es[0] < 0 || es[1] < 0 || es[2] < 0 {
+                    continue
+                }
+                covered = true;
+                // z / w is affine in screen space
+                let c = [bary(es, 0), bary(es, 1), bary(es, 2)];
+                let frag_depth = state.depth_value(pts[0][2] / pts[0][3] * c[0] + pts[1][2] / pts[1][3] * c[1] + pts[2][2] / pts[2][3] * c[2], prim.offset);
+                if !early || test_sample(state, stencil.as_ref(), target, idx * nsamples + s, frag_depth) {
+                    passed[s] = Some(frag_depth);
+                    any_passed = true;
+                }
+            }
+            if covered {
+                if let Some(ref mut coverage) = target.coverage {
+                    coverage[idx] += 1;
+                }
+            }
+            if !any_passed {
+                continue
+            }
+            // the varyings are affine in clip space
+            let c = Vec3f::new(bary(center, 0), bary(center, 1), bary(center, 2));
+            let c = if perspective {
+                let p = Vec3f::new(c.x / pts[0][3], c.y / pts[1][3], c.z / pts[2][3]);
+                p * (1.0 / (p.x + p.y + p.z))
+            } else {
+                c
+            };
+            let mut out = FragmentOutputs::new();
+            let v = S::Varyings::interpolate(varyings, bar[0] * c.x + bar[1] * c.y + bar[2] * c.z);
+            let discard = shader.fragment_outputs(&v, &mut out);
+            if discard {
+                continue
+            }
+            for s in 0..nsamples {
+                if let Some(depth) = passed[s] {
+                    let i = idx * nsamples + s;
+                    if early || test_sample(state, stencil.as_ref(), target, i, depth) {
+                        write_sample(state, stencil.as_ref(), target, i, depth, &out);
+                    }
+                }
+            }
+        }
+        for i in 0..3 {
+            row[i] += step_y[i];
+        }
+    }
+}
+
+/// Stencil then depth test of one sample, running the stencil `fail` and
+/// `depth_fail` operations.
+fn test_sample(state: &RasterState, stencil: Option<&StencilFace>, target: &mut Target, i: usize, depth: f32) -> bool {
+    if let Some(face) = stencil {
+        if !state.stencil.test(face, target.stencil_at(i)) {
+            target.stencil_op(i, &state.stencil, face.fail);
+            return false;
+        }
+    }
+    if !state.depth.func.test(depth, target.depth[i]) {
+        if let Some(face) = stencil {
+            target.stencil_op(i, &state.stencil, face.depth_fail);
+        }
+        return false;
+    }
+    true
+}
+
+/// Store the depth, stencil and colour of a sample that passed the tests.
+fn write_sample(state: &RasterState, stencil: Option<&StencilFace>, target: &mut Target, i: usize, depth: f32, out: &FragmentOutputs) {
+    if state.depth.write {
+        target.depth[i] = depth;
+    }
+    if let Some(face) = stencil {
+        target.stencil_op(i, &state.stencil, face.pass);
+    }
+    target.set(i, out, &state.blend);
+}
+
+/// A fragment of a line or a point, which cover whole pixels.
+#[derive(Debug, Clone, Copy)]
+struct Fragment {
+    x: i32,
+    y: i32,
+    /// whether the stencil front face settings apply
+    front: bool,
+    /// weights relative to the triangle the shader saw
+    bar: Vec3f,
+    /// window depth, before the bias `offset`
+    z: f32,
+    offset: f32,
+}
+
+/// Test, shade and write every sample of the pixel of `frag`. Lines and
+/// points go through here.
+fn shade_pixel<S: Shader>(shader: &S, varyings: &[S::Varyings; 3], state: &RasterState, target: &mut Target, frag: Fragment) {
+    let idx = target.index(frag.x, frag.y);
+    let nsamples = target.samples.len();
+    if let Some(ref mut coverage) = target.coverage {
+        coverage[idx] += 1;
+    }
+    let depth = state.depth_value(frag.z, frag.offset);
+    let stencil = state.stencil_face(frag.front, target);
+    let early = shader.early_z();
+    let mut passed = [false; 8];
+    for s in 0..nsamples {
+        passed[s] = !early || test_sample(state, stencil.as_ref(), target, idx * nsamples + s, depth);
+    }
+    if !passed.iter().any(|&p| p) {
+        return;
+    }
+    let mut out = FragmentOutputs::new();
+    if shader.fragment_outputs(&S::Varyings::interpolate(varyings, frag.bar), &mut out) {
+        return;
+    }
+    for s in 0..nsamples {
+        let i = idx * nsamples + s;
+        if passed[s] && (early || test_sample(state, stencil.as_ref(), target, i, depth)) {
+            write_sample(state, stencil.as_ref(), target, i, depth, &out);
+        }
+    }
+}
+
+/// Walk the major axis of `prim` one pixel centre at a time, from the first
+/// end included to the last excluded, and draw the pixel the line crosses
+/// on the minor axis.
+fn raster_line<S: Shader>(prim: &LinePrim, shader: &S, varyings: &[S::Varyings; 3], state: &RasterState, target: &mut Target, rect: Rect) {
+    let area = prim.bbox.intersect(&rect);
+    if area.is_empty() {
+        return;
+    }
+    let (p0, p1) = (prim.pts[0], prim.pts[1]);
+    let a = [p0[0] / p0[3], p0[1] / p0[3]];
+    let d = [p1[0] / p1[3] - a[0], p1[1] / p1[3] - a[1]];
+    let major = if d[0].abs() >= d[1].abs() { 0 } else { 1 };
+    if d[major] == 0.0 {
+        return;
+    }
+    let perspective = shader.interpolation() == Interpolation::Perspective;
+    let (lo, hi) = (a[major].min(a[major] + d[major]), a[major].max(a[major] + d[major]));
+    for i in ((lo - 0.5).ceil() as i32)..((hi - 0.5).ceil() as i32) {
+        let t = (i as f32 + 0.5 - a[major]) / d[major];
+        let minor = (a[1 - major] + t * d[1 - major]).floor() as i32;
+        let (x, y) = if major == 0 { (i, minor) } else { (minor, i) };
+        if x < area.x0 || x >= area.x1 || y < area.y0 || y >= area.y1 {
+            continue;
+        }
+        let z = p0[2] / p0[3] * (1.0 - t) + p1[2] / p1[3] * t;
+        let (c0, c1) = if perspective {
+            let (c0, c1) = ((1.0 - t) / p0[3], t / p1[3]);
+            (c0 / (c0 + c1), c1 / (c0 + c1))
+        } else {
+            (1.0 - t, t)
+        };
+        let bar = prim.bar[0] * c0 + prim.bar[1] * c1;
+        shade_pixel(shader, varyings, state, target, Fragment { x: x, y: y, front: prim.front, bar: bar, z: z, offset: prim.offset });
+    }
+}
+
+fn raster_point<S: Shader>(prim: &PointPrim, shader: &S, varyings: &[S::Varyings; 3], state: &RasterState, target: &mut Target, rect: Rect) {
+    let area = prim.bbox.intersect(&rect);
+    let z = prim.pos[2] / prim.pos[3];
+    for y in area.y0..area.y1 {
+        for x in area.x0..area.x1 {
+            shade_pixel(shader, varyings, state, target, Fragment { x: x, y: y, front: prim.front, bar: prim.bar, z: z, offset: prim.offset });
+        }
+    }
+}
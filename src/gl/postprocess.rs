@@ -0,0 +1,341 @@
+use gl::*;
+
+/// What a post-processing pass reads: the colour left by the previous pass
+/// and the depth of the geometry pass.
+pub struct PostInput<'a> {
+    pub color: &'a Attachment,
+    /// Per pixel, 0 at the far end of the depth range and 1 at the near end,
+    /// whatever the depth convention. `None` where nothing was drawn.
+    pub depth: &'a [Option<f32>],
+}
+
+impl<'a> PostInput<'a> {
+    pub fn get_width(&self) -> i32 { self.color.get_width() }
+    pub fn get_height(&self) -> i32 { self.color.get_height() }
+    /// Colour of the pixel `x`, `y`, clamped to the edges of the image.
+    pub fn color_at(&self, x: i32, y: i32) -> [f32; 4] {
+        self.color.get(x.max(0).min(self.get_width() - 1), y.max(0).min(self.get_height() - 1))
+    }
+    /// Depth of the pixel `x`, `y`, clamped to the edges of the image.
+    pub fn depth_at(&self, x: i32, y: i32) -> Option<f32> {
+        let x = x.max(0).min(self.get_width() - 1);
+        let y = y.max(0).min(self.get_height() - 1);
+        self.depth[(x + y * self.get_width()) as usize]
+    }
+    /// Bilinear sample at `x`, `y` in pixels, the pixel centres at `.5`.
+    pub fn sample(&self, x: f32, y: f32) -> [f32; 4] {
+        let (fx, fy) = (x - 0.5, y - 0.5);
+        let (x0, y0) = (fx.floor(), fy.floor());
+        let (tx, ty) = (fx - x0, fy - y0);
+        let (x0, y0) = (x0 as i32, y0 as i32);
+        let (a, b, c, d) = (self.color_at(x0, y0), self.color_at(x0 + 1, y0), self.color_at(x0, y0 + 1), self.color_at(x0 + 1, y0 + 1));
+        let mut ret = [0f32; 4];
+        for i in 0..4 {
+            ret[i] = (a[i] * (1.0 - tx) + b[i] * tx) * (1.0 - ty) + (c[i] * (1.0 - tx) + d[i] * tx) * ty;
+        }
+        ret
+    }
+}
+
+/// One image-space pass of a `PostChain`.
+pub trait PostPass {
+    /// Write every pixel of `output`, which has the size and format of
+    /// `input.color`.
+    fn apply(&self, input: &PostInput, output: &mut Attachment);
+}
+
+/// Passes run one after the other on the CPU, each one reading the output of
+/// the previous. Nothing depends on timing or threads: the same input always
+/// gives the same image.
+pub struct PostChain {
+    passes: Vec<Box<dyn PostPass>>,
+}
+
+impl PostChain {
+    pub fn new() -> PostChain {
+        PostChain { passes: vec![] }
+    }
+    /// Append `pass` to the chain.
+    pub fn then<P: PostPass + 'static>(mut self, pass: P) -> PostChain {
+        self.passes.push(Box::new(pass));
+        self
+    }
+    /// Run the chain on `color`, with the `depth` buffer written with
+    /// `state`. The result has the format of `color`.
+    pub fn run(&self, color: &Attachment, depth: &[f32], state: &DepthState) -> Attachment {
+        assert!(depth.len() == (color.get_width() * color.get_height()) as usize,
+                "Error: PostChain::run depth buffer does not match the colour.");
+        let depth: Vec<Option<f32>> = depth.iter().map(|&z| state.closeness(z)).collect();
+        let mut current = color.clone();
+        for pass in self.passes.iter() {
+            let mut output = Attachment::new(color.get_width(), color.get_height(), color.format());
+            pass.apply(&PostInput { color: &current, depth: &depth }, &mut output);
+            current = output;
+        }
+        current
+    }
+    /// `run` on the first colour attachment and the depth of `framebuffer`.
+    #[allow(dead_code)]
+    pub fn run_framebuffer(&self, framebuffer: &Framebuffer, state: &DepthState) -> Attachment {
+        self.run(framebuffer.color(0), framebuffer.depth(), state)
+    }
+}
+
+impl Default for PostChain {
+    fn default() -> PostChain {
+        PostChain::new()
+    }
+}
+
+/// Perceptual luma, the square root of the luminance.
+fn luma(c: [f32; 4]) -> f32 {
+    ColorF::from_bgra(c).luminance().max(0.0).sqrt()
+}
+
+/// Fast approximate anti-aliasing, after Timothy Lottes' FXAA 3.11: finds
+/// the edges from the luma, walks along them to their ends and blends each
+/// pixel with its neighbour across the edge.
+#[derive(Debug, Clone, Copy)]
+pub struct Fxaa {
+    /// smallest contrast, relative to the brightest neighbour, that counts
+    /// as an edge
+    pub edge_threshold: f32,
+    /// contrast below which the dark areas are left alone
+    pub edge_threshold_min: f32,
+    /// how much single pixel details are smoothed, 0 to 1
+    pub subpixel: f32,
+}
+
+/// Step sizes of the search along the edges.
+const FXAA_STEPS: [f32; 12] = [1.0, 1.0, 1.0, 1.0, 1.0, 1.5, 2.0, 2.0, 2.0, 2.0, 4.0, 8.0];
+
+impl Fxaa {
+    pub fn new() -> Fxaa {
+        Fxaa { edge_threshold: 0.125, edge_threshold_min: 0.0312, subpixel: 0.75 }
+    }
+}
+
+impl Default for Fxaa {
+    fn default() -> Fxaa {
+        Fxaa::new()
+    }
+}
+
+impl PostPass for Fxaa {
+    fn apply(&self, input: &PostInput, output: &mut Attachment) {
+        let l = |x: i32, y: i32| luma(input.color_at(x, y));
+        for y in 0..input.get_height() {
+            for x in 0..input.get_width() {
+                let (lm, ln, ls, le, lw) = (l(x, y), l(x, y + 1), l(x, y - 1), l(x + 1, y), l(x - 1, y));
+                let max = lm.max(ln).max(ls).max(le).max(lw);
+                let min = lm.min(ln).min(ls).min(le).min(lw);
+                let range = max - min;
+                if range < self.edge_threshold_min.max(max * self.edge_threshold) {
+                    output.set(x, y, input.color_at(x, y));
+                    continue;
+                }
+                let (lne, lnw, lse, lsw) = (l(x + 1, y + 1), l(x - 1, y + 1), l(x + 1, y - 1), l(x - 1, y - 1));
+
+                let average = (2.0 * (ln + ls + le + lw) + lne + lnw + lse + lsw) / 12.0;
+                let sub = ((average - lm).abs() / range).min(1.0);
+                let sub = (-2.0 * sub + 3.0) * sub * sub;
+                let sub_blend = sub * sub * self.subpixel;
+
+                // a horizontal edge has the luma changing along y
+                let along_y = 2.0 * (ln + ls - 2.0 * lm).abs() + (lne + lse - 2.0 * le).abs() + (lnw + lsw - 2.0 * lw).abs();
+                let along_x = 2.0 * (le + lw - 2.0 * lm).abs() + (lne + lnw - 2.0 * ln).abs() + (lse + lsw - 2.0 * ls).abs();
+                let horizontal = along_y >= along_x;
+                let (l1, l2) = if horizontal { (ls, ln) } else { (lw, le) };
+                let (g1, g2) = (l1 - lm, l2 - lm);
+                let steepest1 = g1.abs() >= g2.abs();
+                let gradient = 0.25 * g1.abs().max(g2.abs());
+                let (step, local) = if steepest1 { (-1.0, 0.5 * (l1 + lm)) } else { (1.0, 0.5 * (l2 + lm)) };
+
+                // half a pixel towards the other side of the edge
+                let (mut cx, mut cy) = (x as f32 + 0.5, y as f32 + 0.5);
+                if horizontal { cy += 0.5 * step } else { cx += 0.5 * step }
+                let (ox, oy) = if horizontal { (1.0, 0.0) } else { (0.0, 1.0) };
+                let (mut p1, mut p2) = ((cx - ox, cy - oy), (cx + ox, cy + oy));
+                let (mut end1, mut end2) = (0.0, 0.0);
+                let (mut done1, mut done2) = (false, false);
+                for &s in FXAA_STEPS.iter() {
+                    if !done1 {
+                        end1 = luma(input.sample(p1.0, p1.1)) - local;
+                        done1 = end1.abs() >= gradient;
+                        if !done1 { p1 = (p1.0 - ox * s, p1.1 - oy * s); }
+                    }
+                    if !done2 {
+                        end2 = luma(input.sample(p2.0, p2.1)) - local;
+                        done2 = end2.abs() >= gradient;
+                        if !done2 { p2 = (p2.0 + ox * s, p2.1 + oy * s); }
+                    }
+                    if done1 && done2 {
+                        break;
+                    }
+                }
+                let d1 = if horizontal { cx - p1.0 } else { cy - p1.1 };
+                let d2 = if horizontal { p2.0 - cx } else { p2.1 - cy };
+                let (dist, end) = if d1 < d2 { (d1, end1) } else { (d2, end2) };
+                let offset = -dist / (d1 + d2) + 0.5;
+                // only blend towards the end that goes the right way
+                let offset = if (end < 0.0) != (lm < local) { offset } else { 0.0 };
+                let offset = offset.max(sub_blend);
+
+                let (mut sx, mut sy) = (x as f32 + 0.5, y as f32 + 0.5);
+                if horizontal { sy += offset * step } else { sx += offset * step }
+                output.set(x, y, input.sample(sx, sy));
+            }
+        }
+    }
+}
+
+/// Outlines where the depth jumps, silhouettes included.
+#[derive(Debug, Clone, Copy)]
+pub struct Outline {
+    /// blue, green, red, alpha; the alpha is the opacity of the line
+    pub color: [f32; 4],
+    /// depth jump, as a fraction of the depth range, that makes an edge
+    pub threshold: f32,
+}
+
+impl Outline {
+    pub fn new() -> Outline {
+        Outline { color: [0.0, 0.0, 0.0, 1.0], threshold: 0.02 }
+    }
+}
+
+impl Default for Outline {
+    fn default() -> Outline {
+        Outline::new()
+    }
+}
+
+impl PostPass for Outline {
+    fn apply(&self, input: &PostInput, output: &mut Attachment) {
+        for y in 0..input.get_height() {
+            for x in 0..input.get_width() {
+                let c = input.color_at(x, y);
+                let here = input.depth_at(x, y);
+                // the drawn side of a silhouette gets the line
+                let edge = here.map_or(false, |d| {
+                    [(1, 0), (-1, 0), (0, 1), (0, -1)].iter().any(|&(dx, dy)| {
+                        input.depth_at(x + dx, y + dy).map_or(true, |o| (o - d).abs() > self.threshold)
+                    })
+                });
+                if edge {
+                    let a = self.color[3];
+                    output.set(x, y, [c[0] + (self.color[0] - c[0]) * a, c[1] + (self.color[1] - c[1]) * a, c[2] + (self.color[2] - c[2]) * a, c[3]]);
+                } else {
+                    output.set(x, y, c);
+                }
+            }
+        }
+    }
+}
+
+/// Light bleeding around the bright areas: what is above `threshold` is
+/// blurred and added back. Works best on HDR colours.
+#[derive(Debug, Clone, Copy)]
+pub struct Bloom {
+    /// luminance above which pixels glow
+    pub threshold: f32,
+    pub intensity: f32,
+    /// of the blur, in pixels
+    pub radius: i32,
+}
+
+impl Bloom {
+    pub fn new() -> Bloom {
+        Bloom { threshold: 1.0, intensity: 0.5, radius: 8 }
+    }
+}
+
+impl Default for Bloom {
+    fn default() -> Bloom {
+        Bloom::new()
+    }
+}
+
+impl PostPass for Bloom {
+    fn apply(&self, input: &PostInput, output: &mut Attachment) {
+        let (w, h) = (input.get_width(), input.get_height());
+        let sigma = (self.radius as f32 / 2.0).max(0.5);
+        let mut weights: Vec<f32> = (-self.radius..self.radius + 1).map(|i| (-(i * i) as f32 / (2.0 * sigma * sigma)).exp()).collect();
+        let total: f32 = weights.iter().sum();
+        for w in weights.iter_mut() {
+            *w /= total;
+        }
+        let bright: Vec<[f32; 4]> = (0..w * h).map(|i| {
+            let c = input.color.get(i % w, i / w);
+            let l = ColorF::from_bgra(c).luminance();
+            if l <= self.threshold {
+                [0.0; 4]
+            } else {
+                let k = (l - self.threshold) / l;
+                [c[0] * k, c[1] * k, c[2] * k, 0.0]
+            }
+        }).collect();
+        let blur = |src: &Vec<[f32; 4]>, dx: i32, dy: i32| -> Vec<[f32; 4]> {
+            (0..w * h).map(|i| {
+                let (x, y) = (i % w, i / w);
+                let mut sum = [0f32; 4];
+                for (k, weight) in weights.iter().enumerate() {
+                    let o = k as i32 - self.radius;
+                    let (sx, sy) = ((x + o * dx).max(0).min(w - 1), (y + o * dy).max(0).min(h - 1));
+                    let s = src[(sx + sy * w) as usize];
+                    for c in 0..4 {
+                        sum[c] += s[c] * weight;
+                    }
+                }
+                sum
+            }).collect()
+        };
+        let glow = blur(&blur(&bright, 1, 0), 0, 1);
+        for y in 0..h {
+            for x in 0..w {
+                let c = input.color_at(x, y);
+                let g = glow[(x + y * w) as usize];
+                output.set(x, y, [c[0] + g[0] * self.intensity, c[1] + g[1] * self.intensity, c[2] + g[2] * self.intensity, c[3]]);
+            }
+        }
+    }
+}
+
+/// Encodes the linear colours for display. The alpha is left linear.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Gamma {
+    /// The sRGB transfer function.
+    Srgb,
+    /// `x^(1 / gamma)`
+    Power(f32),
+}
+
+impl Gamma {
+    pub fn encode(&self, x: f32) -> f32 {
+        let x = x.max(0.0);
+        match *self {
+            Gamma::Srgb => if x <= 0.0031308 { 12.92 * x } else { 1.055 * x.powf(1.0 / 2.4) - 0.055 },
+            Gamma::Power(gamma) => x.powf(1.0 / gamma),
+        }
+    }
+    /// The inverse of `encode`, for colours read from images.
+    #[allow(dead_code)]
+    pub fn decode(&self, x: f32) -> f32 {
+        let x = x.max(0.0);
+        match *self {
+            Gamma::Srgb => if x <= 0.04045 { x / 12.92 } else { ((x + 0.055) / 1.055).powf(2.4) },
+            Gamma::Power(gamma) => x.powf(gamma),
+        }
+    }
+}
+
+impl PostPass for Gamma {
+    fn apply(&self, input: &PostInput, output: &mut Attachment) {
+        for y in 0..input.get_height() {
+            for x in 0..input.get_width() {
+                let c = input.color_at(x, y);
+                output.set(x, y, [self.encode(c[0]), self.encode(c[1]), self.encode(c[2]), c[3]]);
+            }
+        }
+    }
+}
@@ -1,14 +1,112 @@
 use gl::*;
+use std::f32::consts::PI;
 
+/// How the rasterizer weights the varyings of the three vertices.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Interpolation {
+    /// Perspective-correct: the weights are affine in clip space.
+    Perspective,
+    /// The weights are affine in screen space. Only for varyings that are
+    /// screen space quantities already, such as divided positions.
+    NoPerspective,
+}
+
+/// What `Shader::vertex` passes on for each vertex. The rasterizer blends
+/// the values of the three vertices of a triangle with the weights of every
+/// fragment and hands the result to `Shader::fragment`.
+///
+/// Scalars, vectors and tuples of varyings are varyings; `impl_varyings!`
+/// implements it for a struct field by field.
+pub trait Varyings: Clone + Sync {
+    /// The value at the weights `bar`, which sum to 1.
+    fn interpolate(v: &[Self; 3], bar: Vec3f) -> Self;
+}
+
+/// For shaders with nothing to interpolate.
+impl Varyings for () {
+    fn interpolate(_v: &[(); 3], _bar: Vec3f) {}
+}
+
+macro_rules! linear_varyings {
+    ($($t: ty),*) => ($(
+        impl Varyings for $t {
+            fn interpolate(v: &[$t; 3], bar: Vec3f) -> $t {
+                v[0] * bar.x + v[1] * bar.y + v[2] * bar.z
+            }
+        }
+    )*);
+}
+
+linear_varyings!(f32, Vec2f, Vec3f, Vec4f);
+
+macro_rules! tuple_varyings {
+    ($($name: ident $i: tt),*) => (
+        impl<$($name: Varyings),*> Varyings for ($($name,)*) {
+            fn interpolate(v: &[Self; 3], bar: Vec3f) -> Self {
+                ($($name::interpolate(&[v[0].$i.clone(), v[1].$i.clone(), v[2].$i.clone()], bar),)*)
+            }
+        }
+    );
+}
+
+tuple_varyings!(A 0, B 1);
+tuple_varyings!(A 0, B 1, C 2);
+tuple_varyings!(A 0, B 1, C 2, D 3);
+
+/// Implements `Varyings` for a struct by interpolating each of the listed
+/// fields, which must be all of them:
+///
+/// ```ignore
+/// #[derive(Clone)]
+/// struct Surface { uv: Vec2f, normal: Vec3f }
+/// impl_varyings!(Surface { uv, normal });
+/// ```
+#[macro_export]
+macro_rules! impl_varyings {
+    ($t: ident { $($field: ident),* }) => (
+        impl $crate::gl::Varyings for $t {
+            fn interpolate(v: &[$t; 3], bar: $crate::gl::Vec3f) -> $t {
+                $t { $($field: $crate::gl::Varyings::interpolate(&[v[0].$field.clone(), v[1].$field.clone(), v[2].$field.clone()], bar)),* }
+            }
+        }
+    );
+}
+
+/// A programmable stage of the pipeline.
+///
+/// The shader value only holds its uniforms, which stay the same for a whole
+/// draw call: both stages take it by shared reference, and what varies across
+/// a triangle travels from `vertex` to `fragment` as `Varyings`. The same
+/// shader can thus be used by every thread of the rasterizer at once.
 pub trait Shader {
-    fn vertex(&mut self, camera: &super::Camera, model: &mut super::Model, iface: i32, nthvert: i32) -> Vec4f;
-    fn fragment(&self, bar: Vec3f, color: &mut Color) -> bool;
+    type Varyings: Varyings;
+    /// The clip space position of a vertex of the face `iface` of `model`,
+    /// and its varyings.
+    fn vertex(&self, camera: &Camera, model: &Model, iface: i32, nthvert: i32) -> (Vec4f, Self::Varyings);
+    /// The colour of a fragment with the interpolated `varyings`, `true` to
+    /// discard it.
+    fn fragment(&self, varyings: &Self::Varyings, color: &mut Color) -> bool;
+    fn interpolation(&self) -> Interpolation { Interpolation::Perspective }
+    /// Whether the depth and stencil tests may run before `fragment`, which
+    /// then only runs for the fragments that pass. Shaders that never
+    /// discard and leave the depth alone should say so; the others get every
+    /// covered pixel shaded and tested afterwards.
+    fn early_z(&self) -> bool { false }
+    /// Fragment stage for targets with several colour attachments. The
+    /// default writes the colour from `fragment` to all of them.
+    fn fragment_outputs(&self, varyings: &Self::Varyings, out: &mut FragmentOutputs) -> bool {
+        let mut color = Color::new();
+        if self.fragment(varyings, &mut color) {
+            return true;
+        }
+        out.broadcast(color);
+        false
+    }
 }
 
 #[allow(dead_code)]
-pub struct GourauShader {
-    vary_intensity: Vec3f,
-}
+#[derive(Clone)]
+pub struct GourauShader;
 
 macro_rules! clamp {
     ($val: expr, $low: expr, $high: expr) => (
@@ -19,46 +117,41 @@ macro_rules! clamp {
 }
 
 impl GourauShader {
-    pub fn new() -> GourauShader { GourauShader { vary_intensity: Vec3f::zero() } }
+    pub fn new() -> GourauShader { GourauShader }
 }
 
+/// Varyings: the light intensity.
 impl Shader for GourauShader {
-    fn vertex(&mut self, camera: &super::Camera, model: &mut super::Model,iface: i32, nthvert: i32) -> Vec4f {
+    type Varyings = f32;
+    fn vertex(&self, camera: &Camera, model: &Model, iface: i32, nthvert: i32) -> (Vec4f, f32) {
         let gl_vertex = model.face_vert(iface, nthvert).embed(1.0);
-        self.vary_intensity[nthvert as usize] = 0.0.max(model.face_normal(iface, nthvert) * camera.light_dir);
-        camera.viewport * camera.projection * camera.modelview * gl_vertex
+        let intensity = 0.0.max(model.face_normal(iface, nthvert) * camera.light_dir);
+        (camera.viewport * camera.projection * camera.modelview * gl_vertex, intensity)
     }
-    fn fragment(&self, bar: Vec3f, color: &mut Color) -> bool {
-        let intensity = self.vary_intensity * bar;
-        *color = Color::with_color(RGBAColor(255, 255, 255, 255)) * intensity; 
+    fn fragment(&self, intensity: &f32, color: &mut Color) -> bool {
+        *color = Color::with_color(RGBAColor(255, 255, 255, 255)) * *intensity; 
         false
     } 
+    fn early_z(&self) -> bool { true }
 }
 
 #[allow(dead_code)]
-pub struct ToonShader {
-    vary_intensity: Vec3f,
-    vary_mat3: Mat3,
-}
+#[derive(Clone)]
+pub struct ToonShader;
 
 impl ToonShader {
-    pub fn new() -> ToonShader { ToonShader { vary_intensity: Vec3f::zero() , vary_mat3: Mat3::zero() } }
+    pub fn new() -> ToonShader { ToonShader }
 }
 
+/// Varyings: the light intensity, quantized per fragment.
 impl Shader for ToonShader {
-    fn vertex(&mut self, camera: &super::Camera, model: &mut super::Model, iface: i32, nthvert: i32) -> Vec4f {
-        let mut gl_vertex = model.face_vert(iface, nthvert).embed(1.0);
-        gl_vertex = camera.projection * camera.modelview * gl_vertex;
-        let proj = (gl_vertex / gl_vertex[3]).proj3();
-        for i in 0..self.vary_mat3.ncols() as usize {
-            self.vary_mat3[i][nthvert as usize] = proj[i];
-        } 
-        self.vary_intensity[nthvert as usize] = clamp!(model.face_normal(iface, nthvert) * camera.light_dir, 0.0, 1.0);
-        gl_vertex = camera.viewport * gl_vertex;
-        gl_vertex
-    }
-    fn fragment(&self, bar: Vec3f, color: &mut Color) -> bool {
-        let mut intensity = self.vary_intensity * bar;
+    type Varyings = f32;
+    fn vertex(&self, camera: &Camera, model: &Model, iface: i32, nthvert: i32) -> (Vec4f, f32) {
+        let gl_vertex = camera.viewport * camera.projection * camera.modelview * model.face_vert(iface, nthvert).embed(1.0);
+        (gl_vertex, clamp!(model.face_normal(iface, nthvert) * camera.light_dir, 0.0, 1.0))
+    }
+    fn fragment(&self, intensity: &f32, color: &mut Color) -> bool {
+        let mut intensity = *intensity;
         if intensity > 0.85 { intensity = 1.0; }
         else if intensity > 0.60 { intensity = 0.80; }
         else if intensity > 0.45 { intensity = 0.60; }
@@ -67,79 +160,77 @@ impl Shader for ToonShader {
         *color = Color::with_color(RGBAColor(255,255,255,255)) * intensity;
         false 
     }    
+    fn early_z(&self) -> bool { true }
 }
 
 #[allow(dead_code)]
-pub struct FlatShader {
-    vary_mat3: Mat3,
-    light_dir: Vec3f,
-}
+#[derive(Clone)]
+pub struct FlatShader;
 
 impl FlatShader {
-    pub fn new() -> Self { FlatShader { vary_mat3: Mat3::zero(), light_dir: Vec3f::zero() } }
+    pub fn new() -> Self { FlatShader }
 }
 
+/// Varyings: the light intensity of the face, the same at its three
+/// vertices. Each vertex works it out from the whole face, projected.
 impl Shader for FlatShader {
-    fn vertex(&mut self, camera: &super::Camera, model: &mut super::Model, iface: i32, nthvert: i32) -> Vec4f {
-        self.light_dir = camera.light_dir;
-        let mut gl_vertex = model.face_vert(iface, nthvert).embed(1.0);
-        gl_vertex = camera.projection * camera.modelview * gl_vertex;
-        let proj = (gl_vertex / gl_vertex[3]).proj3();
-        for i in 0..self.vary_mat3.ncols() as usize {
-            self.vary_mat3[nthvert as usize][i] = proj[i];
-        } 
-        gl_vertex = camera.viewport * gl_vertex;
-        gl_vertex
-    }
-    #[allow(unused_variables)]
-    fn fragment(&self, bar: Vec3f, color: &mut Color) -> bool {
-        let n = cross(Vec3f::from_vec(&self.vary_mat3[1]) - Vec3f::from_vec(&self.vary_mat3[0]), Vec3f::from_vec(&self.vary_mat3[2]) - Vec3f::from_vec(&self.vary_mat3[0])).normalize();
-        let intensity = clamp!(n * self.light_dir, 0.0, 1.0);
-        *color = Color::with_color(RGBAColor(255,255,255,255)) * intensity;
+    type Varyings = f32;
+    fn vertex(&self, camera: &Camera, model: &Model, iface: i32, nthvert: i32) -> (Vec4f, f32) {
+        let mut proj = [Vec3f::zero(); 3];
+        for j in 0..3 {
+            let gl_vertex = camera.projection * camera.modelview * model.face_vert(iface, j as i32).embed(1.0);
+            proj[j] = (gl_vertex / gl_vertex[3]).proj3();
+        }
+        let n = cross(proj[1] - proj[0], proj[2] - proj[0]).normalize();
+        let gl_vertex = camera.viewport * camera.projection * camera.modelview * model.face_vert(iface, nthvert).embed(1.0);
+        (gl_vertex, clamp!(n * camera.light_dir, 0.0, 1.0))
+    }
+    fn fragment(&self, intensity: &f32, color: &mut Color) -> bool {
+        *color = Color::with_color(RGBAColor(255,255,255,255)) * *intensity;
         false 
     }    
+    fn early_z(&self) -> bool { true }
 }
 
 #[allow(dead_code)]
+#[derive(Clone)]
 pub struct DepthShader{
-    vary_mat3: Mat3,
     depth: f32,
 }
 
 impl DepthShader {
     #[allow(dead_code)]
     pub fn new(depth: f32) -> DepthShader {
-        DepthShader { vary_mat3: Mat3::zero(), depth: depth }
+        DepthShader { depth: depth }
     } 
 }
 
+/// Varyings: the window depth.
 impl Shader for DepthShader {
-    fn vertex(&mut self, camera: &super::Camera, model: &mut super::Model, iface: i32, nthvert: i32) -> Vec4f {
+    type Varyings = f32;
+    fn vertex(&self, camera: &Camera, model: &Model, iface: i32, nthvert: i32) -> (Vec4f, f32) {
         let mut gl_vertex = model.face_vert(iface, nthvert).embed(1.0);
         gl_vertex = camera.viewport * camera.projection * camera.modelview * gl_vertex;
-        let proj = (gl_vertex / gl_vertex[3]).proj3();
-        self.vary_mat3[0][nthvert as usize] = proj[1];
-        self.vary_mat3[1][nthvert as usize] = proj[1];
-        self.vary_mat3[2][nthvert as usize] = proj[2];
-        gl_vertex    
-    }
-    fn fragment(&self, bar: Vec3f, color: &mut Color) -> bool {
-        let p = self.vary_mat3 * bar;
-        *color = Color::with_color(RGBAColor(255, 255, 255, 255)) * (p.z / self.depth);
+        (gl_vertex, gl_vertex[2] / gl_vertex[3])
+    }
+    fn fragment(&self, z: &f32, color: &mut Color) -> bool {
+        *color = Color::with_color(RGBAColor(255, 255, 255, 255)) * (*z / self.depth);
         false
     }
+    fn interpolation(&self) -> Interpolation { Interpolation::NoPerspective }
+    fn early_z(&self) -> bool { true }
 }
 
 #[allow(dead_code)]
+#[derive(Clone)]
 pub struct IShader<'a> {
     uniform_m: Mat4,
     uniform_mit: Mat4,
     uniform_mshadow: Mat4,
-    varying_uv: [Vec3f;2],
-    vary_mat3: Mat3,
-    model_cache: Option<Model>,
+    model_cache: Option<&'a Model>,
     light_dir: Vec3f,        
-    shadowbuffer: Option<&'a Vec<f32>>,
+    shadowbuffer: Option<&'a [f32]>,
+    occlusion: Option<&'a Attachment>,
     height: usize,
     width: usize,
 }
@@ -149,9 +240,10 @@ pub struct IShaderBuilder<'a> {
     m: Mat4,
     mit: Mat4,
     mshadow: Mat4,
-    model_cache: Option<Model>,
+    model_cache: Option<&'a Model>,
     light_dir: Vec3f,        
-    shadowbuffer: Option<&'a Vec<f32>>,
+    shadowbuffer: Option<&'a [f32]>,
+    occlusion: Option<&'a Attachment>,
     height: usize,
     width: usize,    
 }
@@ -166,66 +258,68 @@ impl<'a> IShaderBuilder<'a> {
             model_cache: None,
             light_dir: Vec3f::zero(),
             shadowbuffer: None,
+            occlusion: None,
             height: 0,
             width: 0,
         }
     }
     #[allow(dead_code)]
-    pub fn model(mut self, model: Model) -> IShaderBuilder<'a> { self.model_cache = Some(model); self }
+    pub fn model(mut self, model: &'a Model) -> IShaderBuilder<'a> { self.model_cache = Some(model); self }
     #[allow(dead_code)]
     pub fn light_dir(mut self, light_dir: Vec3f) -> IShaderBuilder<'a>{ self.light_dir = light_dir; self }
     #[allow(dead_code)]
     pub fn size(mut self, w: usize, h: usize) -> IShaderBuilder<'a> { self.width = w; self.height = h; self }
     #[allow(dead_code)]
-    pub fn shadowbuffer(mut self, buffer: &'a Vec<f32>) -> IShaderBuilder<'a> { self.shadowbuffer = Some(buffer); self }
+    pub fn shadowbuffer(mut self, buffer: &'a [f32]) -> IShaderBuilder<'a> { self.shadowbuffer = Some(buffer); self }
+    /// Screen-space ambient occlusion of the view, from `Ssao::occlusion`,
+    /// scaling the ambient term.
+    #[allow(dead_code)]
+    pub fn occlusion(mut self, occlusion: &'a Attachment) -> IShaderBuilder<'a> { self.occlusion = Some(occlusion); self }
     #[allow(dead_code)]
     pub fn build(self) -> IShader<'a> {
         IShader {
             uniform_m: self.m,
             uniform_mit: self.mit,
             uniform_mshadow: self.mshadow,
-            varying_uv: [Vec3f::zero();2],
-            vary_mat3: Mat3::zero(),
             model_cache: self.model_cache,
             light_dir: self.light_dir,        
             shadowbuffer: self.shadowbuffer,
+            occlusion: self.occlusion,
             height: self.height,
             width: self.width,    
         }
     }
 }
 
-impl<'a> Shader for IShader<'a> {
-    fn vertex(&mut self, camera: &super::Camera, model: &mut super::Model, iface: i32, nthvert: i32) -> Vec4f {
-        let tmp = model.uv(iface as usize, nthvert as usize);
-        
-        self.varying_uv[0][nthvert as usize] = tmp[0];
-        self.varying_uv[1][nthvert as usize] = tmp[1];
-        
-        let gl_vertex = camera.viewport * camera.projection * camera.modelview * model.face_vert(iface, nthvert).embed(1.0);
-        let proj = (gl_vertex / gl_vertex[3]).proj3();
-          
-        self.vary_mat3[0][nthvert as usize] = proj[0];
-        self.vary_mat3[1][nthvert as usize] = proj[1];
-        self.vary_mat3[2][nthvert as usize] = proj[2];
-        
-        gl_vertex
-    }
-    fn fragment(&self, bar: Vec3f, color: &mut Color) -> bool {
-        let mut sb_p = self.uniform_mshadow * (self.vary_mat3 * bar).embed(1.0);
+/// Varyings of `IShader`.
+#[derive(Clone)]
+pub struct TexturedVaryings {
+    uv: Vec2f,
+    /// window position, not yet divided by `w`
+    pos: Vec4f,
+}
+
+impl_varyings!(TexturedVaryings { uv, pos });
+
+impl<'a> IShader<'a> {
+    /// Lit colour, blue, green, red, on the `0..255` scale of the textures
+    /// but not clamped to it. `None` without a model.
+    fn shade(&self, v: &TexturedVaryings) -> Option<[f32; 3]> {
+        let window = v.pos.proj3() * (1.0 / v.pos[3]);
+        let mut sb_p = self.uniform_mshadow * window.embed(1.0);
         sb_p = sb_p / sb_p[3];
-        let idx = (sb_p[0] + sb_p[1] * self.width as f32) as usize;
-        let shadow = 0.3 + 0.7 * if self.shadowbuffer.map(|x| x[idx]).unwrap() < sb_p[2] { 1.0 } else { 0.0 };
-        let uv = {
-            let mut ret = Vec2f::zero();
-            ret[0] = self.varying_uv[0] * bar;
-            ret[1] = self.varying_uv[1] * bar;
-            ret
+        // what projects outside of the shadow map, or has none, is lit
+        let (x, y) = (sb_p[0].floor(), sb_p[1].floor());
+        let inside = x >= 0.0 && y >= 0.0 && x < self.width as f32 && y < self.height as f32;
+        let lit = match self.shadowbuffer {
+            Some(buffer) if inside => buffer.get(x as usize + y as usize * self.width).map_or(true, |&z| z < sb_p[2]),
+            _ => true,
         };
-        
-        let (tmp, spec_exp, c) = match self.model_cache {
-            None => return true,
-            Some(ref model) => (model.normal(uv).embed(1.0), model.specular(uv), model.diffuse(uv)),   
+        let shadow = 0.3 + 0.7 * if lit { 1.0 } else { 0.0 };
+        let uv = v.uv;
+        let (tmp, spec_exp, c, baked) = match self.model_cache {
+            None => return None,
+            Some(model) => (model.normal(uv).embed(1.0), model.specular(uv), model.diffuse(uv), model.occlusion(uv)),   
         };
         
         let n = (self.uniform_mit * tmp).normalize().proj3();
@@ -233,12 +327,337 @@ impl<'a> Shader for IShader<'a> {
         let r = (n * (n * l * 0.2) - l).normalize();
         let spec = 0.0f32.max(r.z).powf(spec_exp);
         let diff = 0.0f32.max(n * l);
-        *color = Color::with_color(RGBAColor(255,255,255,255));
+        let ambient = 20.0 * baked * match self.occlusion {
+            Some(ao) => ao.get((window.x as i32).max(0).min(ao.get_width() - 1), (window.y as i32).max(0).min(ao.get_height() - 1))[0],
+            None => 1.0,
+        };
+        let mut lit = [0f32; 3];
+        for i in 0..3 {
+            lit[i] = ambient + c[i] as f32 * shadow * (1.2 * diff + 0.6 * spec);
+        }
+        Some(lit)
+    }
+    fn clamped(lit: [f32; 3]) -> Color {
+        let mut color = Color::with_color(RGBAColor(255,255,255,255));
         for i in 0..3 {
-            color[i] = 255.0f32.min(20.0 + c[i] as f32 * shadow * (1.2 * diff + 0.6 * spec)) as u8;
+            color[i] = 255.0f32.min(lit[i]) as u8;
+        }
+        color
+    }
+}
+
+impl<'a> Shader for IShader<'a> {
+    type Varyings = TexturedVaryings;
+    fn vertex(&self, camera: &Camera, model: &Model, iface: i32, nthvert: i32) -> (Vec4f, TexturedVaryings) {
+        let gl_vertex = camera.viewport * camera.projection * camera.modelview * model.face_vert(iface, nthvert).embed(1.0);
+        // keep the undivided position, it is divided once interpolated
+        (gl_vertex, TexturedVaryings { uv: model.uv(iface as usize, nthvert as usize), pos: gl_vertex })
+    }
+    fn fragment(&self, v: &TexturedVaryings, color: &mut Color) -> bool {
+        match self.shade(v) {
+            None => true,
+            Some(lit) => {
+                *color = IShader::clamped(lit);
+                false
+            }
+        }
+    }
+    /// The 8 bit colour of `fragment`, and the unclamped one for HDR targets.
+    fn fragment_outputs(&self, v: &TexturedVaryings, out: &mut FragmentOutputs) -> bool {
+        let lit = match self.shade(v) {
+            None => return true,
+            Some(lit) => lit,
+        };
+        out.broadcast(IShader::clamped(lit));
+        out.broadcast_hdr(ColorF::new(lit[2] / 255.0, lit[1] / 255.0, lit[0] / 255.0));
+        false
+    }
+    /// Only discards when there is no model to shade.
+    fn early_z(&self) -> bool { self.model_cache.is_some() }
+}
+
+/// Varyings of the shaders lighting a model: texture coordinates, and the
+/// position, normal and tangent in model space, where the lights are. The
+/// tangent is the one of `Model::tangent`, handedness in `w`.
+#[derive(Clone)]
+pub struct Surface {
+    pub uv: Vec2f,
+    pub pos: Vec3f,
+    pub normal: Vec3f,
+    pub tangent: Vec4f,
+}
+
+impl_varyings!(Surface { uv, pos, normal, tangent });
+
+impl Surface {
+    /// Clip space position and varyings of a vertex of `model`.
+    pub fn vertex(camera: &Camera, model: &Model, iface: i32, nthvert: i32) -> (Vec4f, Surface) {
+        let pos = model.face_vert(iface, nthvert);
+        let surface = Surface {
+            uv: model.uv(iface as usize, nthvert as usize),
+            pos: pos,
+            normal: model.face_normal(iface, nthvert),
+            tangent: model.tangent(iface, nthvert),
+        };
+        (camera.viewport * camera.projection * camera.modelview * pos.embed(1.0), surface)
+    }
+    /// Unit normal to shade with. A tangent space normal map of `model` is
+    /// decoded in the basis of the interpolated tangent, bitangent and
+    /// normal, which follows the surface wherever it is moved; an object
+    /// space one is taken as it is. Without either, the interpolated normal.
+    pub fn shading_normal(&self, model: &Model) -> Vec3f {
+        let n = self.normal.normalize();
+        let t = self.tangent.proj3();
+        let t = t - n * (n * t);
+        if model.has_tangent_normal_map() && t.norm() > 1.0e-6 {
+            let t = t.normalize();
+            // the sign survives the interpolation across mirrored seams
+            let b = cross(n, t) * if self.tangent[3] < 0.0 { -1.0 } else { 1.0 };
+            let m = model.normal_tangent(self.uv);
+            (t * m.x + b * m.y + n * m.z).normalize()
+        } else if model.has_normal_map() {
+            model.normal(self.uv).normalize()
+        } else {
+            n
+        }
+    }
+}
+
+/// Blinn-Phong shading of a textured model by any number of lights, in model
+/// space. The diffuse map gives the colour and the specular map the exponent
+/// of the highlights, none where it is 0; the gloss map scales them and the
+/// glow map adds the light the surface emits. The normal is the one of
+/// `Surface::shading_normal`.
+#[derive(Clone)]
+pub struct BlinnPhongShader<'a> {
+    model: &'a Model,
+    lights: &'a [Light],
+    eye: Vec3f,
+    /// light reaching every surface whatever its orientation, scaled by the
+    /// occlusion map of the model
+    pub ambient: f32,
+    /// weight of the highlights, which are the colour of the lights
+    pub specular: f32,
+    /// weight of the glow map
+    pub emission: f32,
+}
+
+impl<'a> BlinnPhongShader<'a> {
+    #[allow(dead_code)]
+    pub fn new(model: &'a Model, lights: &'a [Light], eye: Vec3f) -> BlinnPhongShader<'a> {
+        BlinnPhongShader { model: model, lights: lights, eye: eye, ambient: 0.08, specular: 0.6, emission: 1.0 }
+    }
+    /// Unclamped colour of the surface.
+    pub fn lit(&self, s: &Surface) -> ColorF {
+        let n = s.shading_normal(self.model);
+        let v = (self.eye - s.pos).normalize();
+        let exp = self.model.specular(s.uv);
+        let ambient = self.ambient * self.model.occlusion(s.uv);
+        let mut diffuse = ColorF::new(ambient, ambient, ambient);
+        let mut highlight = ColorF::new(0.0, 0.0, 0.0);
+        for light in self.lights.iter() {
+            let (l, c) = light.incident(s.pos);
+            let cos = n * l;
+            if cos <= 0.0 {
+                continue;
+            }
+            let c = ColorF::new(c.x, c.y, c.z);
+            diffuse = diffuse + c * cos;
+            if exp > 0.0 {
+                let h = (l + v).normalize();
+                highlight = highlight + c * (n * h).max(0.0).powf(exp);
+            }
+        }
+        let glow = ColorF::from_color(self.model.glow(s.uv)) * self.emission;
+        ColorF::from_color(self.model.diffuse(s.uv)) * diffuse + highlight * (self.specular * self.model.gloss(s.uv)) + glow
+    }
+}
+
+/// Varyings: a `Surface`.
+impl<'a> Shader for BlinnPhongShader<'a> {
+    type Varyings = Surface;
+    fn vertex(&self, camera: &Camera, model: &Model, iface: i32, nthvert: i32) -> (Vec4f, Surface) {
+        Surface::vertex(camera, model, iface, nthvert)
+    }
+    fn fragment(&self, s: &Surface, color: &mut Color) -> bool {
+        *color = self.lit(s).to_color();
+        false
+    }
+    /// The 8 bit colour of `fragment`, and the unclamped one for HDR targets.
+    fn fragment_outputs(&self, s: &Surface, out: &mut FragmentOutputs) -> bool {
+        let lit = self.lit(s);
+        out.broadcast(lit.to_color());
+        out.broadcast_hdr(lit);
+        false
+    }
+    fn early_z(&self) -> bool { true }
+}
+
+/// Material of a `PbrShader`. As in glTF, each factor multiplies the map of
+/// the model that holds the same input, and is the input itself when the
+/// model has no such map: the base colour goes with the diffuse map, the
+/// metalness and the roughness with the metallic-roughness map. Without one,
+/// a gloss map gives the roughness as one minus the gloss. The emission
+/// only scales the glow map.
+#[derive(Debug, Clone, Copy)]
+pub struct PbrMaterial {
+    /// linear red, green, blue; the diffuse map is decoded from sRGB
+    pub base_color: ColorF,
+    pub metallic: f32,
+    /// perceptual roughness, squared for the distribution
+    pub roughness: f32,
+    pub emissive: f32,
+}
+
+impl PbrMaterial {
+    /// Every factor at one, which leaves the maps as they are.
+    pub fn new() -> PbrMaterial {
+        PbrMaterial { base_color: ColorF::new(1.0, 1.0, 1.0), metallic: 1.0, roughness: 1.0, emissive: 1.0 }
+    }
+    #[allow(dead_code)]
+    pub fn with_base_color(mut self, base_color: ColorF) -> PbrMaterial { self.base_color = base_color; self }
+    #[allow(dead_code)]
+    pub fn with_metallic(mut self, metallic: f32) -> PbrMaterial { self.metallic = metallic; self }
+    #[allow(dead_code)]
+    pub fn with_roughness(mut self, roughness: f32) -> PbrMaterial { self.roughness = roughness; self }
+    #[allow(dead_code)]
+    pub fn with_emissive(mut self, emissive: f32) -> PbrMaterial { self.emissive = emissive; self }
+}
+
+impl Default for PbrMaterial {
+    fn default() -> PbrMaterial {
+        PbrMaterial::new()
+    }
+}
+
+/// Physically based shading of a model by any number of lights, in model
+/// space, with the Cook-Torrance microfacet model: GGX distribution, Smith
+/// geometry term with the Schlick-GGX approximation and Schlick Fresnel,
+/// over a Lambertian diffuse term.
+///
+/// The BRDF is scaled by pi, so that a white light reaching a white matte
+/// surface head on gives white, as with `BlinnPhongShader`, which shades
+/// with the same normal. HDR targets get the linear colour, 8 bit ones the
+/// colour encoded with the sRGB curve.
+#[derive(Clone)]
+pub struct PbrShader<'a> {
+    model: &'a Model,
+    lights: &'a [Light],
+    eye: Vec3f,
+    pub material: PbrMaterial,
+    /// light reaching every surface whatever its orientation, scaled by the
+    /// occlusion map of the model
+    pub ambient: f32,
+}
+
+impl<'a> PbrShader<'a> {
+    #[allow(dead_code)]
+    pub fn new(model: &'a Model, lights: &'a [Light], eye: Vec3f, material: PbrMaterial) -> PbrShader<'a> {
+        PbrShader { model: model, lights: lights, eye: eye, material: material, ambient: 0.03 }
+    }
+    /// Linear colour of the surface.
+    pub fn lit(&self, s: &Surface) -> ColorF {
+        let mut base = self.material.base_color;
+        if self.model.has_diffuse_map() {
+            base = base * PbrShader::decoded(self.model.diffuse(s.uv));
+        }
+        let (metallic, roughness) = match self.model.metal_rough(s.uv) {
+            Some((m, r)) => (self.material.metallic * m, self.material.roughness * r),
+            None if self.model.has_texture(&TextureSlot::Gloss) => (self.material.metallic, self.material.roughness * (1.0 - self.model.gloss(s.uv))),
+            None => (self.material.metallic, self.material.roughness),
+        };
+        // a perfectly smooth surface would only reflect a point light at one
+        // angle
+        let alpha = roughness.max(0.045) * roughness.max(0.045);
+        let k = (roughness + 1.0) * (roughness + 1.0) / 8.0;
+        let albedo = [base.r, base.g, base.b];
+        // dielectrics reflect 4% head on, metals tint the reflection
+        let f0 = [0, 1, 2].iter().map(|&i| 0.04 + (albedo[i] - 0.04) * metallic).collect::<Vec<f32>>();
+
+        let n = s.shading_normal(self.model);
+        let v = (self.eye - s.pos).normalize();
+        let nv = (n * v).max(1.0e-4);
+        let ambient = self.ambient * self.model.occlusion(s.uv);
+        let mut sum = [0f32; 3];
+        for light in self.lights.iter() {
+            let (l, c) = light.incident(s.pos);
+            let nl = n * l;
+            if nl <= 0.0 {
+                continue;
+            }
+            let h = (l + v).normalize();
+            let nh = (n * h).max(0.0);
+            let vh = (v * h).max(0.0);
+            let d = nh * nh * (alpha * alpha - 1.0) + 1.0;
+            let distribution = alpha * alpha / (PI * d * d);
+            let geometry = nv / (nv * (1.0 - k) + k) * (nl / (nl * (1.0 - k) + k));
+            let c = [c.x, c.y, c.z];
+            for i in 0..3 {
+                let fresnel = f0[i] + (1.0 - f0[i]) * (1.0 - vh).powi(5);
+                let specular = distribution * geometry * fresnel / (4.0 * nv * nl);
+                let diffuse = (1.0 - fresnel) * (1.0 - metallic) * albedo[i] / PI;
+                sum[i] += PI * (diffuse + specular) * c[i] * nl;
+            }
         }
-        let wc = *color;
-        let st = true;
+        let glow = PbrShader::decoded(self.model.glow(s.uv)) * self.material.emissive;
+        ColorF::rgba(sum[0] + ambient * base.r + glow.r, sum[1] + ambient * base.g + glow.g, sum[2] + ambient * base.b + glow.b, 1.0)
+    }
+    /// Linear colour of a texel of an sRGB texture.
+    fn decoded(color: Color) -> ColorF {
+        let c = ColorF::from_color(color);
+        ColorF::new(Gamma::Srgb.decode(c.r), Gamma::Srgb.decode(c.g), Gamma::Srgb.decode(c.b))
+    }
+    fn encoded(lit: ColorF) -> Color {
+        ColorF::rgba(Gamma::Srgb.encode(lit.r), Gamma::Srgb.encode(lit.g), Gamma::Srgb.encode(lit.b), lit.a).to_color()
+    }
+}
+
+/// Varyings: a `Surface`.
+impl<'a> Shader for PbrShader<'a> {
+    type Varyings = Surface;
+    fn vertex(&self, camera: &Camera, model: &Model, iface: i32, nthvert: i32) -> (Vec4f, Surface) {
+        Surface::vertex(camera, model, iface, nthvert)
+    }
+    fn fragment(&self, s: &Surface, color: &mut Color) -> bool {
+        *color = PbrShader::encoded(self.lit(s));
+        false
+    }
+    fn fragment_outputs(&self, s: &Surface, out: &mut FragmentOutputs) -> bool {
+        let lit = self.lit(s);
+        out.broadcast(PbrShader::encoded(lit));
+        out.broadcast_hdr(lit);
+        false
+    }
+    fn early_z(&self) -> bool { true }
+}
+
+/// Draws a model with one of its textures as it is, unlit, the texture
+/// found by the name of its slot, as `TextureSlot::from_name` reads it. The
+/// model is black where it has no such texture.
+#[derive(Clone)]
+pub struct TextureShader<'a> {
+    model: &'a Model,
+    pub slot: TextureSlot,
+}
+
+impl<'a> TextureShader<'a> {
+    #[allow(dead_code)]
+    pub fn new(model: &'a Model, name: &str) -> TextureShader<'a> {
+        TextureShader { model: model, slot: TextureSlot::from_name(name) }
+    }
+}
+
+/// Varyings: the texture coordinates.
+impl<'a> Shader for TextureShader<'a> {
+    type Varyings = Vec2f;
+    fn vertex(&self, camera: &Camera, model: &Model, iface: i32, nthvert: i32) -> (Vec4f, Vec2f) {
+        let pos = model.face_vert(iface, nthvert).embed(1.0);
+        (camera.viewport * camera.projection * camera.modelview * pos, model.uv(iface as usize, nthvert as usize))
+    }
+    fn fragment(&self, uv: &Vec2f, color: &mut Color) -> bool {
+        // through ColorF so that grayscale textures come out gray
+        *color = self.model.sample(&self.slot, *uv).map_or(ColorF::new(0.0, 0.0, 0.0), ColorF::from_color).to_color();
         false
     }
-}
\ No newline at end of file
+    fn early_z(&self) -> bool { true }
+}
@@ -0,0 +1,241 @@
+use gl::*;
+use std::f32::consts::PI;
+
+/// How `Ssao` decides what occludes a pixel.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum AoMethod {
+    /// Points taken in the hemisphere above the surface, turned along the
+    /// normal rebuilt from the depth, count when the depth buffer has
+    /// something in front of them.
+    Hemisphere,
+    /// Walks the depth buffer in several directions around the pixel and
+    /// keeps the highest elevation angle seen above the tangent plane, as in
+    /// horizon-based ambient occlusion.
+    Horizon,
+}
+
+/// Screen-space ambient occlusion from the depth buffer alone.
+///
+/// The depth is seen as a height field in pixels: the whole depth range
+/// spans `depth_scale` pixels. `occlusion` gives the ambient light reaching
+/// every pixel in an `R32F` attachment, which can be written out as a
+/// grayscale image or given to the lighting shaders; as a `PostPass` it
+/// darkens the colour with it.
+#[derive(Debug, Clone, Copy)]
+pub struct Ssao {
+    pub method: AoMethod,
+    /// in pixels
+    pub radius: f32,
+    /// points for `Hemisphere`, directions for `Horizon`
+    pub samples: usize,
+    /// steps along every direction of `Horizon`
+    pub steps: usize,
+    /// height, in pixels, below which nothing occludes, against self
+    /// occlusion of the surfaces
+    pub bias: f32,
+    /// 0 leaves the image alone, 1 makes fully occluded pixels black
+    pub strength: f32,
+    /// pixels covered by the whole depth range, the width of the buffer
+    /// when `None`
+    pub depth_scale: Option<f32>,
+    /// half the size of the box blur hiding the rotation pattern, 0 for none
+    pub blur: i32,
+}
+
+/// Rotation of the samples, in sixteenths of a turn, repeated every 4x4
+/// pixels; the blur averages it out.
+const ROTATIONS: [f32; 16] = [0.0, 8.0, 2.0, 10.0, 12.0, 4.0, 14.0, 6.0, 3.0, 11.0, 1.0, 9.0, 15.0, 7.0, 13.0, 5.0];
+
+impl Ssao {
+    pub fn new() -> Ssao {
+        Ssao {
+            method: AoMethod::Hemisphere,
+            radius: 16.0,
+            samples: 16,
+            steps: 4,
+            bias: 1.0,
+            strength: 0.8,
+            depth_scale: None,
+            blur: 2,
+        }
+    }
+    #[allow(dead_code)]
+    pub fn with_method(mut self, method: AoMethod) -> Ssao {
+        self.method = method;
+        self
+    }
+
+    /// Ambient light reaching every pixel, 1 when nothing occludes it, in
+    /// an `R32F` attachment. `depth` was written with `state`.
+    #[allow(dead_code)]
+    pub fn occlusion(&self, depth: &[f32], width: i32, height: i32, state: &DepthState) -> Attachment {
+        assert!(depth.len() == (width * height) as usize, "Error: Ssao::occlusion depth buffer does not match the size.");
+        let closeness: Vec<Option<f32>> = depth.iter().map(|&z| state.closeness(z)).collect();
+        self.occlusion_of(&closeness, width, height)
+    }
+
+    /// `occlusion` from the depth of a `PostInput`.
+    pub fn occlusion_of(&self, closeness: &[Option<f32>], width: i32, height: i32) -> Attachment {
+        let scale = self.depth_scale.unwrap_or(width as f32);
+        let field = HeightField { height: closeness.iter().map(|d| d.map(|d| d * scale)).collect(), w: width, h: height };
+        let mut raw = vec![1f32; (width * height) as usize];
+        for y in 0..height {
+            for x in 0..width {
+                raw[(x + y * width) as usize] = self.visibility(&field, x, y);
+            }
+        }
+        let mut ret = Attachment::new(width, height, Format::R32F);
+        for y in 0..height {
+            for x in 0..width {
+                ret.set(x, y, [self.blurred(&field, &raw, x, y); 4]);
+            }
+        }
+        ret
+    }
+
+    fn visibility(&self, field: &HeightField, x: i32, y: i32) -> f32 {
+        let h = match field.at(x, y) {
+            Some(h) => h,
+            None => return 1.0,
+        };
+        let n = field.normal(x, y, h);
+        let rotation = ROTATIONS[((x & 3) + (y & 3) * 4) as usize] / 16.0 * 2.0 * PI;
+        let occlusion = match self.method {
+            AoMethod::Hemisphere => self.hemisphere(field, x, y, h, n, rotation),
+            AoMethod::Horizon => self.horizon(field, x, y, h, n, rotation),
+        };
+        1.0 - self.strength * occlusion.min(1.0)
+    }
+
+    /// Fraction of the points of the hemisphere with geometry in front.
+    fn hemisphere(&self, field: &HeightField, x: i32, y: i32, h: f32, n: Vec3f, rotation: f32) -> f32 {
+        let r = Vec3f::new(rotation.cos(), rotation.sin(), 0.0);
+        let t = (r - n * (r * n)).normalize();
+        let b = cross(n, t);
+        let p = Vec3f::new(x as f32 + 0.5, y as f32 + 0.5, h);
+        let mut occluded = 0.0;
+        for i in 0..self.samples {
+            // spread over the hemisphere, denser towards the centre
+            let u = (i as f32 + 0.5) / self.samples as f32;
+            let phi = i as f32 * 2.399963;
+            let cos_theta = radical_inverse(i as u32 + 1);
+            let sin_theta = (1.0 - cos_theta * cos_theta).sqrt();
+            let scale = 0.1 + 0.9 * u * u;
+            let k = (t * (sin_theta * phi.cos()) + b * (sin_theta * phi.sin()) + n * cos_theta) * (scale * self.radius);
+            let s = p + k;
+            if let Some(stored) = field.at(s.x.floor() as i32, s.y.floor() as i32) {
+                if stored >= s.z + self.bias {
+                    // what lies far in front is another object
+                    let range = (self.radius / (h - stored).abs()).min(1.0);
+                    occluded += range * range * (3.0 - 2.0 * range);
+                }
+            }
+        }
+        occluded / self.samples as f32
+    }
+
+    /// Average over the directions of the sine of the highest elevation above
+    /// the tangent plane, fading with the distance.
+    fn horizon(&self, field: &HeightField, x: i32, y: i32, h: f32, n: Vec3f, rotation: f32) -> f32 {
+        let mut occluded = 0.0;
+        for i in 0..self.samples {
+            let angle = rotation + 2.0 * PI * i as f32 / self.samples as f32;
+            let (dx, dy) = (angle.cos(), angle.sin());
+            // the tangent plane rises by this much per pixel along the direction
+            let slope = -(n.x * dx + n.y * dy) / n.z;
+            let mut top = slope / (1.0 + slope * slope).sqrt();
+            let mut sum = 0.0;
+            for s in 1..self.steps + 1 {
+                let dist = self.radius * s as f32 / self.steps as f32;
+                let stored = match field.at((x as f32 + 0.5 + dx * dist).floor() as i32, (y as f32 + 0.5 + dy * dist).floor() as i32) {
+                    Some(stored) => stored - self.bias,
+                    None => continue,
+                };
+                let dz = stored - h;
+                let elevation = dz / (dz * dz + dist * dist).sqrt();
+                if elevation > top {
+                    let fade = 1.0 - (dist * dist) / (self.radius * self.radius);
+                    sum += (elevation - top) * fade.max(0.0);
+                    top = elevation;
+                }
+            }
+            occluded += sum;
+        }
+        occluded / self.samples as f32
+    }
+
+    /// Mean of the covered pixels around `x`, `y`.
+    fn blurred(&self, field: &HeightField, raw: &[f32], x: i32, y: i32) -> f32 {
+        if field.at(x, y).is_none() {
+            return 1.0;
+        }
+        let (mut sum, mut count) = (0.0, 0);
+        for j in y - self.blur..y + self.blur + 1 {
+            for i in x - self.blur..x + self.blur + 1 {
+                if field.at(i, j).is_some() {
+                    sum += raw[(i + j * field.w) as usize];
+                    count += 1;
+                }
+            }
+        }
+        sum / count as f32
+    }
+}
+
+impl Default for Ssao {
+    fn default() -> Ssao {
+        Ssao::new()
+    }
+}
+
+impl PostPass for Ssao {
+    fn apply(&self, input: &PostInput, output: &mut Attachment) {
+        let occlusion = self.occlusion_of(input.depth, input.get_width(), input.get_height());
+        for y in 0..input.get_height() {
+            for x in 0..input.get_width() {
+                let v = occlusion.get(x, y)[0];
+                let c = input.color_at(x, y);
+                output.set(x, y, [c[0] * v, c[1] * v, c[2] * v, c[3]]);
+            }
+        }
+    }
+}
+
+/// The depth in pixels, `None` outside the image and where nothing was drawn.
+struct HeightField {
+    height: Vec<Option<f32>>,
+    w: i32,
+    h: i32,
+}
+
+impl HeightField {
+    fn at(&self, x: i32, y: i32) -> Option<f32> {
+        if x < 0 || y < 0 || x >= self.w || y >= self.h {
+            return None;
+        }
+        self.height[(x + y * self.w) as usize]
+    }
+    /// Unit normal, towards the viewer, from the differences with the
+    /// neighbours; the smaller one on each axis so that edges stay sharp.
+    fn normal(&self, x: i32, y: i32, h: f32) -> Vec3f {
+        let slope = |a: Option<f32>, b: Option<f32>| match (a, b) {
+            (Some(a), Some(b)) => if (h - a).abs() < (b - h).abs() { h - a } else { b - h },
+            (Some(a), None) => h - a,
+            (None, Some(b)) => b - h,
+            (None, None) => 0.0,
+        };
+        let dx = slope(self.at(x - 1, y), self.at(x + 1, y));
+        let dy = slope(self.at(x, y - 1), self.at(x, y + 1));
+        Vec3f::new(-dx, -dy, 1.0).normalize()
+    }
+}
+
+/// Van der Corput sequence in base 2.
+fn radical_inverse(mut i: u32) -> f32 {
+    i = (i << 16) | (i >> 16);
+    i = ((i & 0x55555555) << 1) | ((i & 0xAAAAAAAA) >> 1);
+    i = ((i & 0x33333333) << 2) | ((i & 0xCCCCCCCC) >> 2);
+    i = ((i & 0x0F0F0F0F) << 4) | ((i & 0xF0F0F0F0) >> 4);
+    i = ((i & 0x00FF00FF) << 8) | ((i & 0xFF00FF00) >> 8);
+    i as f32 / 4294967296.0
+}
@@ -0,0 +1,114 @@
+use gl::depth::CompareFunc;
+
+/// What happens to the stored stencil value.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum StencilOp {
+    Keep,
+    Zero,
+    /// store the reference value
+    Replace,
+    /// add one, saturating at 255
+    Incr,
+    /// subtract one, saturating at 0
+    Decr,
+    IncrWrap,
+    DecrWrap,
+    Invert,
+}
+
+impl StencilOp {
+    fn apply(&self, stored: u8, reference: u8) -> u8 {
+        match *self {
+            StencilOp::Keep => stored,
+            StencilOp::Zero => 0,
+            StencilOp::Replace => reference,
+            StencilOp::Incr => stored.saturating_add(1),
+            StencilOp::Decr => stored.saturating_sub(1),
+            StencilOp::IncrWrap => stored.wrapping_add(1),
+            StencilOp::DecrWrap => stored.wrapping_sub(1),
+            StencilOp::Invert => !stored,
+        }
+    }
+}
+
+/// Stencil test and operations for the faces of one winding.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct StencilFace {
+    pub func: CompareFunc,
+    /// the stencil test failed
+    pub fail: StencilOp,
+    /// the stencil test passed, the depth test failed
+    pub depth_fail: StencilOp,
+    /// both tests passed
+    pub pass: StencilOp,
+}
+
+impl StencilFace {
+    pub fn new() -> StencilFace {
+        StencilFace {
+            func: CompareFunc::Always,
+            fail: StencilOp::Keep,
+            depth_fail: StencilOp::Keep,
+            pass: StencilOp::Keep,
+        }
+    }
+}
+
+impl Default for StencilFace {
+    fn default() -> StencilFace {
+        StencilFace::new()
+    }
+}
+
+/// Stencil configuration of a `Pipeline`.
+///
+/// A sample passes when `(reference & read_mask) <func> (stored & read_mask)`.
+/// Only the bits of `write_mask` are changed by the operations. Front and back
+/// faces are told apart with `Pipeline::front_face`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct StencilState {
+    pub enabled: bool,
+    pub reference: u8,
+    pub read_mask: u8,
+    pub write_mask: u8,
+    pub front: StencilFace,
+    pub back: StencilFace,
+}
+
+impl StencilState {
+    pub fn new() -> StencilState {
+        StencilState {
+            enabled: false,
+            reference: 0,
+            read_mask: 0xff,
+            write_mask: 0xff,
+            front: StencilFace::new(),
+            back: StencilFace::new(),
+        }
+    }
+    /// Use `face` for both windings and enable the test.
+    #[allow(dead_code)]
+    pub fn both(mut self, face: StencilFace) -> StencilState {
+        self.enabled = true;
+        self.front = face;
+        self.back = face;
+        self
+    }
+    pub fn face(&self, front: bool) -> &StencilFace {
+        if front { &self.front } else { &self.back }
+    }
+    pub fn test(&self, face: &StencilFace, stored: u8) -> bool {
+        face.func.test(self.reference & self.read_mask, stored & self.read_mask)
+    }
+    /// New stored value after `op`.
+    pub fn update(&self, op: StencilOp, stored: u8) -> u8 {
+        let value = op.apply(stored, self.reference);
+        (stored & !self.write_mask) | (value & self.write_mask)
+    }
+}
+
+impl Default for StencilState {
+    fn default() -> StencilState {
+        StencilState::new()
+    }
+}
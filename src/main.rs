@@ -16,35 +16,81 @@ fn main() {
     let up = Vec3f::new(0,1,0);
     let light_dir = Vec3f::new(1,1,0).normalize();
     
-    let mut model = if args.len() == 1 { model::Model::open_with_texture("obj/african_head.obj") }
-                   else if args.len() == 2 { 
-                       if args[1].find(".obj") != None { model::Model::open_with_texture(&args[1]) } 
-                       else { panic!("Error: Parameter: {} is not an obj file.", args[1]); }
-                   }
-                   else { panic!("Too many parameters input."); };
+    // bake-ao [file.obj] [out.tga] only bakes the ambient occlusion map of
+    // the model, in the layout of its textures
+    if args.len() > 1 && args[1] == "bake-ao" {
+        if args.len() > 4 { panic!("Too many parameters input."); }
+        let obj_file = args.get(2).cloned().unwrap_or("obj/african_head.obj".to_string());
+        let out_file = args.get(3).cloned().unwrap_or("ao_baked.tga".to_string());
+        let model = model::Model::open(&obj_file);
+        let mut baker = OcclusionBaker::new();
+        baker.threads = std::thread::available_parallelism().map(|n| n.get()).unwrap_or(1);
+        let mut map = baker.bake(&model);
+        map.flip_vertically().unwrap();
+        map.write_tga_file(&out_file, gl::WRITE_RLE_FILE).unwrap();
+        println!("Finished");
+        return;
+    }
+
+    // --hdr <file.pfm|file.hdr> also writes the unclamped render,
+    // --post writes post.tga, the render with ambient occlusion and FXAA,
+    // --ao writes ao.tga, the ambient occlusion alone,
+    // --phong writes phong.tga, Blinn-Phong shading by several lights,
+    // --pbr writes pbr.tga, physically based shading by the same lights,
+    // --texture <slot> writes texture.tga, the model with the texture of the
+    // slot, such as glow or gloss, unlit
+    let mut hdr_file = None;
+    let mut post = false;
+    let mut ao = false;
+    let mut phong = false;
+    let mut pbr = false;
+    let mut texture_slot = None;
+    let mut obj_file = None;
+    let mut i = 1;
+    while i < args.len() {
+        if args[i] == "--hdr" {
+            if i + 1 == args.len() { panic!("Error: --hdr needs a .pfm or .hdr file name."); }
+            hdr_file = Some(args[i + 1].clone());
+            i += 1;
+        }
+        else if args[i] == "--post" { post = true; }
+        else if args[i] == "--ao" { ao = true; }
+        else if args[i] == "--phong" { phong = true; }
+        else if args[i] == "--pbr" { pbr = true; }
+        else if args[i] == "--texture" {
+            if i + 1 == args.len() { panic!("Error: --texture needs the name of a texture slot."); }
+            texture_slot = Some(args[i + 1].clone());
+            i += 1;
+        }
+        else if args[i].find(".obj") != None && obj_file.is_none() { obj_file = Some(args[i].clone()); }
+        else if obj_file.is_none() { panic!("Error: Parameter: {} is not an obj file.", args[i]); }
+        else { panic!("Too many parameters input."); }
+        i += 1;
+    }
+    let model = model::Model::open_with_texture(&obj_file.unwrap_or("obj/african_head.obj".to_string()));
     
-    let mut shadowbuffer = vec![std::f32::MIN; (width * height) as usize];
+    let mut shadow = Framebuffer::new(width, height).with_color(Format::R32F);
     let mut zbuffer = vec![std::f32::MIN; (width * height) as usize];
     let mut CameraOne = Camera::new(depth);
+    let mut pipeline = Pipeline::new(width, height, depth);
+    pipeline.cull = CullMode::Back;
+    pipeline.threads = std::thread::available_parallelism().map(|n| n.get()).unwrap_or(1);
     CameraOne.set_light_dir(light_dir);
     
     {
-        let mut depth_image = gl::TGAImage::with_info(width as isize, height as isize, tga_image::RGB);
         CameraOne.lookat(light_dir, center, up);
         CameraOne.viewport(width / 8, height / 8, width * 3 / 4, height * 3 / 4);
         CameraOne.projection(0.0);
         
         
-        let mut shader = gl::shader::DepthShader::new(depth);
-        // let mut shader = gl::shader::GourauShader::new();
-        for i in 0..model.nfaces() {
-            let mut screen_coords = [Vec4f::zero();3];
-            for j in 0..3 {
-                screen_coords[j] = shader.vertex(&CameraOne, &mut model, i as i32, j as i32); 
-            }
-            gl::triangle(&mut screen_coords, &mut shader, &mut depth_image, &mut shadowbuffer);
-        }
+        let shader = gl::shader::DepthShader::new(depth);
+        // let shader = gl::shader::GourauShader::new();
+        // push the occluders away from the light, against shadow acne
+        pipeline.depth_bias = DepthBias::with(-10.0, -2.0);
+        pipeline.draw(&CameraOne, &model, &shader, &mut shadow);
+        pipeline.depth_bias = DepthBias::new();
         
+        let mut depth_image = shadow.color(0).to_image();
         depth_image.flip_vertically().unwrap();
         depth_image.write_tga_file("depth.tga", gl::WRITE_RLE_FILE).unwrap();
     }
@@ -56,19 +102,70 @@ fn main() {
         CameraOne.viewport(width / 8, height / 8, width * 3 / 4, height * 3 / 4);
         CameraOne.projection(-1.0 / (eye - center).norm() as f32);
         
-        let mut shader = gl::shader::IShaderBuilder::new(CameraOne.modelview, (CameraOne.projection * CameraOne.modelview).inverse().transpose(), m * (CameraOne.viewport * CameraOne.projection * CameraOne.modelview).inverse())
-            .light_dir(light_dir).size(width as usize, height as usize).model(model.clone()).shadowbuffer(&shadowbuffer).build();
+        let shader = gl::shader::IShaderBuilder::new(CameraOne.modelview, (CameraOne.projection * CameraOne.modelview).inverse().transpose(), m * (CameraOne.viewport * CameraOne.projection * CameraOne.modelview).inverse())
+            .light_dir(light_dir).size(width as usize, height as usize).model(&model).shadowbuffer(shadow.depth()).build();
 
-        let mut screen_coords = [Vec4f::zero();3];
-        for i in 0..model.nfaces() {
-            for j in 0..3 {
-                screen_coords[j] = shader.vertex(&CameraOne, &mut model, i as i32, j as i32);
-            }
-            gl::triangle(&mut screen_coords, &shader, &mut image, &mut zbuffer);
-        }
+        pipeline.draw(&CameraOne, &model, &shader, Target::new(&mut image, &mut zbuffer));
         
+        // the viewport maps the depth range to as many pixels as the width
+        let mut ssao = Ssao::new();
+        ssao.depth_scale = Some((width * 3 / 4) as f32);
+        if ao {
+            let mut ao_image = ssao.occlusion(&zbuffer, width, height, &pipeline.depth).to_image();
+            ao_image.flip_vertically().unwrap();
+            ao_image.write_tga_file("ao.tga", gl::WRITE_RLE_FILE).unwrap();
+        }
+        if post {
+            let chain = PostChain::new().then(ssao).then(Fxaa::new());
+            let mut post_image = chain.run(&Attachment::from_image(&image), &zbuffer, &pipeline.depth).to_image();
+            post_image.flip_vertically().unwrap();
+            post_image.write_tga_file("post.tga", gl::WRITE_RLE_FILE).unwrap();
+        }
         image.flip_vertically().unwrap();
         image.write_tga_file("output.tga", gl::WRITE_RLE_FILE).unwrap();
+
+        if let Some(hdr_file) = hdr_file {
+            let mut hdr = Framebuffer::new(width, height).with_color(Format::Rgba32F);
+            pipeline.draw(&CameraOne, &model, &shader, &mut hdr);
+            if hdr_file.ends_with(".pfm") { hdr.color(0).write_pfm(&hdr_file).unwrap(); }
+            else if hdr_file.ends_with(".hdr") { hdr.color(0).write_hdr(&hdr_file).unwrap(); }
+            else { panic!("Error: {} is neither a .pfm nor a .hdr file.", hdr_file); }
+        }
+
+        // a dim key light, a warm point light on the left and a blue spot
+        // from above
+        let lights = [
+            Light::directional(light_dir, Vec3f::new(1.0, 1.0, 1.0)).with_intensity(0.6),
+            Light::point(Vec3f::new(-1.5, 0.0, 1.0), Vec3f::new(1.0, 0.6, 0.3), 3.0).with_intensity(1.5),
+            Light::spot(Vec3f::new(0.0, 2.0, 1.0), Vec3f::new(0.0, -2.0, -1.0), Vec3f::new(0.3, 0.5, 1.0), 5.0, 0.25, 0.4).with_intensity(1.5),
+        ];
+        if phong {
+            let mut phong_image = gl::TGAImage::with_info(width as isize, height as isize, tga_image::RGB);
+            let mut phong_z = vec![std::f32::MIN; (width * height) as usize];
+            pipeline.draw(&CameraOne, &model, &BlinnPhongShader::new(&model, &lights, eye), Target::new(&mut phong_image, &mut phong_z));
+            phong_image.flip_vertically().unwrap();
+            phong_image.write_tga_file("phong.tga", gl::WRITE_RLE_FILE).unwrap();
+        }
+
+        if pbr {
+            // the maps of the model when it has them, a half rough dielectric
+            // otherwise
+            let material = PbrMaterial::new().with_metallic(if model.has_metal_rough_map() { 1.0 } else { 0.0 })
+                .with_roughness(if model.has_metal_rough_map() { 1.0 } else { 0.5 });
+            let mut pbr_image = gl::TGAImage::with_info(width as isize, height as isize, tga_image::RGB);
+            let mut pbr_z = vec![std::f32::MIN; (width * height) as usize];
+            pipeline.draw(&CameraOne, &model, &PbrShader::new(&model, &lights, eye, material), Target::new(&mut pbr_image, &mut pbr_z));
+            pbr_image.flip_vertically().unwrap();
+            pbr_image.write_tga_file("pbr.tga", gl::WRITE_RLE_FILE).unwrap();
+        }
+
+        if let Some(slot) = texture_slot {
+            let mut texture_image = gl::TGAImage::with_info(width as isize, height as isize, tga_image::RGB);
+            let mut texture_z = vec![std::f32::MIN; (width * height) as usize];
+            pipeline.draw(&CameraOne, &model, &TextureShader::new(&model, &slot), Target::new(&mut texture_image, &mut texture_z));
+            texture_image.flip_vertically().unwrap();
+            texture_image.write_tga_file("texture.tga", gl::WRITE_RLE_FILE).unwrap();
+        }
     }
     
     
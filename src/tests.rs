@@ -164,4 +164,1727 @@ mod test_mat4 {
         let res = mat * v4f;
         assert_eq!(res, Vec4f::new([1.0,3.0,1.0,3.0]));
     }  
-}
\ No newline at end of file
+}
+#[cfg(test)]
+mod test_clip {
+    use super::super::gl::*;
+    use super::common;
+    #[test]
+    fn test_inside() {
+        let frustum = Frustum::new(800, 800);
+        let pts = [Vec4f::new([10.0, 10.0, 5.0, 1.0]), Vec4f::new([100.0, 10.0, 5.0, 1.0]), Vec4f::new([10.0, 100.0, 5.0, 1.0])];
+        let tris = frustum.clip_triangle(&pts);
+        assert_eq!(tris.len(), 1);
+        assert_eq!(tris[0][1].pos, pts[1]);
+        assert_eq!(tris[0][2].bar, Vec3f::new(0, 0, 1));
+    }
+    #[test]
+    fn test_outside() {
+        let frustum = Frustum::new(800, 800);
+        let pts = [Vec4f::new([-10.0, 10.0, 5.0, 1.0]), Vec4f::new([-100.0, 10.0, 5.0, 1.0]), Vec4f::new([-10.0, 100.0, 5.0, 1.0])];
+        assert!(frustum.clip_triangle(&pts).is_empty());
+    }
+    #[test]
+    fn test_behind_camera() {
+        let frustum = Frustum::new(800, 800);
+        let pts = [Vec4f::new([400.0, 400.0, 1000.0, 1.0]), Vec4f::new([500.0, 400.0, 1000.0, 1.0]), Vec4f::new([400.0, 500.0, -500.0, -0.5])];
+        let tris = frustum.clip_triangle(&pts);
+        assert!(!tris.is_empty());
+        for tri in tris.iter() {
+            for v in tri.iter() {
+                assert!(v.pos[3] > 0.0);
+                assert!((v.bar.x + v.bar.y + v.bar.z - 1.0).abs() < 1e-5);
+                // a new vertex is the same weighted sum of the originals as its varyings
+                let p = pts[0] * v.bar.x + pts[1] * v.bar.y + pts[2] * v.bar.z;
+                for i in 0..4 {
+                    assert!((p[i] - v.pos[i]).abs() < 1e-2);
+                }
+            }
+        }
+    }
+    #[test]
+    fn test_triangle_near_plane() {
+        let mut pipeline = Pipeline::new(64, 64, 255.0);
+        let mut image = TGAImage::with_info(64, 64, tga_image::RGB);
+        let mut zbuffer = vec![std::f32::MIN; 64 * 64];
+        let shader = GourauShader::new();
+        let pts = [Vec4f::new([16.0, 16.0, 100.0, 1.0]), Vec4f::new([48.0, 16.0, 100.0, 1.0]), Vec4f::new([-32.0, -32.0, -100.0, -1.0])];
+        pipeline.triangle(&pts, &[0.0; 3], &shader, Target::new(&mut image, &mut zbuffer));
+        assert!(zbuffer.iter().any(|&z| z != std::f32::MIN));
+    }
+    #[test]
+    fn test_camera_close() {
+        let model = Model::open("obj/african_head.obj");
+        // the head, nose included, seen from `eye` units away; the frustum is
+        // much bigger than the viewport to keep the sides out of the way
+        let clipped = |eye: f32| {
+            let mut camera = Camera::new(255.0);
+            camera.lookat(Vec3f::new(0.0, 0.0, eye), Vec3f::new(0, 0, 0), Vec3f::new(0, 1, 0));
+            camera.viewport(2000, 2000, 64, 64);
+            camera.projection(-1.0 / eye);
+            let frustum = Frustum::new(4064, 4064);
+            let mut ret = 0;
+            for i in 0..model.nfaces() as i32 {
+                let mut pts = [Vec4f::zero(); 3];
+                for j in 0..3 {
+                    pts[j] = camera.viewport * camera.projection * camera.modelview * model.face_vert(i, j as i32).embed(1.0);
+                }
+                let tris = frustum.clip_triangle(&pts);
+                if tris.len() != 1 || tris[0][0].pos != pts[0] || tris[0][2].pos != pts[2] {
+                    ret += 1;
+                }
+                for tri in tris.iter() {
+                    assert!(tri.iter().all(|v| v.pos[3] >= NEAR_W * 0.999));
+                }
+            }
+            ret
+        };
+        assert_eq!(clipped(1.5), 0);
+        // from inside the head, only what is behind the eye goes
+        let inside = clipped(0.5);
+        assert!(inside > 0 && inside < model.nfaces());
+    }
+    #[test]
+    fn test_shadow_outside_map() {
+        let model = common::textured_head();
+        let mut camera = Camera::new(255.0);
+        camera.viewport(0, 0, 8, 8);
+        // every point lands far above the 8x8 shadow map
+        let mut mshadow = Mat4::identity();
+        mshadow[(1, 3)] = 1000.0;
+        let shadow = vec![std::f32::MAX; 8 * 8];
+        let lit = |shadowbuffer: &[f32], mshadow: Mat4| {
+            let shader = IShaderBuilder::new(Mat4::identity(), Mat4::identity(), mshadow)
+                .model(&model).light_dir(Vec3f::new(0, 0, 1)).size(8, 8).shadowbuffer(shadowbuffer).build();
+            let (_, v) = shader.vertex(&camera, &model, 0, 0);
+            let mut color = Color::new();
+            assert!(!shader.fragment(&v, &mut color));
+            color
+        };
+        // what the map does not cover is lit, even with everything in the
+        // map in shadow
+        let outside = lit(&shadow, mshadow);
+        let unshadowed = lit(&vec![std::f32::MIN; 8 * 8], Mat4::identity());
+        assert_eq!(outside.raw(), unshadowed.raw());
+    }
+}
+
+/// Scene set up shared by the rendering tests.
+#[cfg(test)]
+mod common {
+    use super::super::gl::*;
+
+    /// Where the head is seen from.
+    pub fn eye() -> Vec3f {
+        Vec3f::new(1, 1, 3)
+    }
+    /// Looks at the origin from `eye`, lit along (1, 1, 1), with the unit
+    /// cube around the origin filling the `size` square at `x`, `y`.
+    pub fn look(eye: Vec3f, x: i32, y: i32, size: i32) -> Camera {
+        let mut camera = Camera::new(255.0);
+        camera.set_light_dir(Vec3f::new(1, 1, 1).normalize());
+        camera.lookat(eye, Vec3f::new(0, 0, 0), Vec3f::new(0, 1, 0));
+        camera.viewport(x, y, size, size);
+        camera.projection(-1.0 / 3.0);
+        camera
+    }
+    /// The head seen from `eye`, in the middle of a 120x120 target.
+    pub fn camera() -> Camera {
+        look(eye(), 10, 10, 100)
+    }
+    pub fn head() -> Model {
+        Model::open("obj/african_head.obj")
+    }
+    pub fn textured_head() -> Model {
+        Model::open_with_texture("obj/african_head.obj")
+    }
+    /// A pipeline, an RGB image and a cleared z-buffer, all `w` x `h`.
+    pub fn targets(w: i32, h: i32) -> (Pipeline, TGAImage, Vec<f32>) {
+        (Pipeline::new(w, h, 255.0), TGAImage::with_info(w as isize, h as isize, tga_image::RGB), vec![std::f32::MIN; (w * h) as usize])
+    }
+
+    /// Paints everything in `color`.
+    #[derive(Clone)]
+    pub struct FlatShader {
+        pub color: ColorType,
+    }
+
+    impl Shader for FlatShader {
+        type Varyings = ();
+        fn vertex(&self, camera: &Camera, model: &Model, iface: i32, nthvert: i32) -> (Vec4f, ()) {
+            let v = model.face_vert(iface, nthvert);
+            (camera.viewport * camera.projection * camera.modelview * v.embed(1.0), ())
+        }
+        fn fragment(&self, _v: &(), color: &mut Color) -> bool {
+            *color = Color::with_color(self.color);
+            false
+        }
+    }
+}
+
+#[cfg(test)]
+mod test_interpolation {
+    use super::super::gl::*;
+    use super::common;
+
+    // a quad tilted away from the camera, textured with a 2x2 checkerboard
+    struct CheckerShader {
+        quad: [Vec3f; 4],
+        uv: [Vec2f; 4],
+        interpolation: Interpolation,
+    }
+
+    impl Shader for CheckerShader {
+        type Varyings = Vec2f;
+        fn vertex(&self, camera: &Camera, _model: &Model, iface: i32, nthvert: i32) -> (Vec4f, Vec2f) {
+            let idx = [[0, 1, 2], [0, 2, 3]][iface as usize][nthvert as usize];
+            (camera.viewport * camera.projection * camera.modelview * self.quad[idx].embed(1.0), self.uv[idx])
+        }
+        fn fragment(&self, uv: &Vec2f, color: &mut Color) -> bool {
+            let cell = (uv.x * 2.0) as i32 + (uv.y * 2.0) as i32;
+            *color = Color::with_color(if cell % 2 == 0 { RGBColor(255, 255, 255) } else { RGBColor(0, 0, 0) });
+            false
+        }
+        fn interpolation(&self) -> Interpolation { self.interpolation }
+    }
+
+    fn render(interpolation: Interpolation) -> TGAImage {
+        let camera = common::look(Vec3f::new(0, 0, 3), 0, 0, 64);
+        let shader = CheckerShader {
+            quad: [Vec3f::new(-1.0, -1.0, 0.5), Vec3f::new(1.0, -1.0, 0.5), Vec3f::new(1.0, 1.0, -1.4), Vec3f::new(-1.0, 1.0, -1.4)],
+            uv: [Vec2f::new(0, 0), Vec2f::new(1, 0), Vec2f::new(1, 1), Vec2f::new(0, 1)],
+            interpolation: interpolation,
+        };
+        let model = Model::open("obj/cube.obj");
+        let (mut pipeline, mut image, mut zbuffer) = common::targets(64, 64);
+        for i in 0..2 {
+            let mut pts = [Vec4f::zero(); 3];
+            let mut uv = [Vec2f::zero(); 3];
+            for j in 0..3 {
+                let (p, v) = shader.vertex(&camera, &model, i, j as i32);
+                pts[j] = p;
+                uv[j] = v;
+            }
+            pipeline.triangle(&pts, &uv, &shader, Target::new(&mut image, &mut zbuffer));
+        }
+        image
+    }
+
+    #[test]
+    fn test_perspective_checkerboard() {
+        // v = 0.5 is the world point (0, 0, -0.45), which projects onto row 32
+        let image = render(Interpolation::Perspective);
+        assert_eq!(image.get(24, 28)[0], 255);
+        assert_eq!(image.get(24, 36)[0], 0);
+    }
+    #[test]
+    fn test_noperspective_checkerboard() {
+        // affine weights put v = 0.5 halfway between the edges on screen, around row 24
+        let image = render(Interpolation::NoPerspective);
+        assert_eq!(image.get(24, 20)[0], 255);
+        assert_eq!(image.get(24, 28)[0], 0);
+    }
+
+    #[derive(Clone)]
+    struct Surface {
+        uv: Vec2f,
+        lit: (f32, Vec3f),
+    }
+
+    impl_varyings!(Surface { uv, lit });
+
+    #[test]
+    fn test_varyings() {
+        let corner = |u: f32, v: f32, i: f32| Surface { uv: Vec2f::new(u, v), lit: (i, Vec3f::new(u, v, i)) };
+        let v = [corner(0.0, 0.0, 1.0), corner(1.0, 0.0, 0.5), corner(0.0, 1.0, 0.0)];
+        let s = Surface::interpolate(&v, Vec3f::new(0.5, 0.25, 0.25));
+        assert_eq!((s.uv.x, s.uv.y), (0.25, 0.25));
+        assert_eq!(s.lit.0, 0.625);
+        assert_eq!((s.lit.1.x, s.lit.1.y, s.lit.1.z), (0.25, 0.25, 0.625));
+        // a vertex gets its own values back
+        let s = Surface::interpolate(&v, Vec3f::new(0.0, 1.0, 0.0));
+        assert_eq!((s.uv.x, s.uv.y, s.lit.0), (1.0, 0.0, 0.5));
+    }
+}
+
+#[cfg(test)]
+mod test_tiles {
+    use super::super::gl::*;
+    use super::common;
+
+    fn render(threads: usize) -> (TGAImage, Vec<f32>) {
+        let camera = common::look(common::eye(), 20, 10, 180);
+        let model = common::head();
+        let (mut pipeline, mut image, mut zbuffer) = common::targets(200, 170);
+        pipeline.threads = threads;
+        let shader = GourauShader::new();
+        pipeline.draw(&camera, &model, &shader, Target::new(&mut image, &mut zbuffer));
+        (image, zbuffer)
+    }
+
+    #[test]
+    fn test_same_as_serial() {
+        let (mut serial, serial_z) = render(1);
+        for &threads in [2, 3, 8].iter() {
+            let (mut tiled, tiled_z) = render(threads);
+            assert!(serial.buffer() == tiled.buffer());
+            assert!(serial_z == tiled_z);
+        }
+        assert!(serial_z.iter().any(|&z| z != std::f32::MIN));
+    }
+}
+
+#[cfg(test)]
+mod test_fill_rule {
+    use super::super::gl::*;
+
+    fn draw(pipeline: &mut Pipeline, tris: &[[Vec2f; 3]]) {
+        let mut image = TGAImage::with_info(64, 48, tga_image::RGB);
+        let mut zbuffer = vec![std::f32::MIN; 64 * 48];
+        let shader = GourauShader::new();
+        for t in tris.iter() {
+            let pts = [Vec4f::new([t[0].x, t[0].y, 0.0, 1.0]), Vec4f::new([t[1].x, t[1].y, 0.0, 1.0]), Vec4f::new([t[2].x, t[2].y, 0.0, 1.0])];
+            pipeline.triangle(&pts, &[0.0; 3], &shader, Target::new(&mut image, &mut zbuffer));
+        }
+    }
+
+    #[test]
+    fn test_quad_covers_once() {
+        let mut pipeline = Pipeline::new(64, 48, 255.0);
+        pipeline.set_coverage_debug(true);
+        let (a, b, c, d) = (Vec2f::new(0, 0), Vec2f::new(64, 0), Vec2f::new(64, 48), Vec2f::new(0, 48));
+        draw(&mut pipeline, &[[a, b, c], [a, c, d]]);
+        assert!(pipeline.coverage().unwrap().iter().all(|&n| n == 1));
+    }
+    #[test]
+    fn test_mesh_covers_once() {
+        // a jittered grid, with vertices landing on pixel centres and edges
+        // going through them
+        let (nx, ny) = (8, 6);
+        let mut seed = 12345u32;
+        let mut grid = vec![];
+        for j in 0..(ny + 1) {
+            for i in 0..(nx + 1) {
+                seed = seed.wrapping_mul(1103515245).wrapping_add(12345);
+                let jitter = if i == 0 || j == 0 || i == nx || j == ny { 0.0 } else { ((seed >> 16) % 7) as f32 - 3.0 };
+                grid.push(Vec2f::new(i as f32 * 8.0 + jitter + 0.5, j as f32 * 8.0 - jitter * 0.5 + 0.5));
+            }
+        }
+        let mut tris = vec![];
+        for j in 0..ny {
+            for i in 0..nx {
+                let v = |x: usize, y: usize| grid[x + y * (nx + 1)];
+                tris.push([v(i, j), v(i + 1, j), v(i + 1, j + 1)]);
+                tris.push([v(i, j), v(i + 1, j + 1), v(i, j + 1)]);
+            }
+        }
+        let mut pipeline = Pipeline::new(64, 48, 255.0);
+        pipeline.set_coverage_debug(true);
+        draw(&mut pipeline, &tris);
+        // the mesh spans [0.5, 64.5] x [0.5, 48.5]: the centres of the first
+        // column are on a left edge and kept, those of the first row are on
+        // a bottom edge and left out
+        let coverage = pipeline.coverage().unwrap();
+        for y in 0..48 {
+            for x in 0..64 {
+                let expected = if y == 0 { 0 } else { 1 };
+                assert_eq!(coverage[x + y * 64], expected, "pixel ({}, {})", x, y);
+            }
+        }
+    }
+    #[test]
+    fn test_thin_triangle() {
+        let mut pipeline = Pipeline::new(64, 48, 255.0);
+        pipeline.set_coverage_debug(true);
+        draw(&mut pipeline, &[[Vec2f::new(0.0, 10.4), Vec2f::new(64.0, 10.6), Vec2f::new(0.0, 10.6)]]);
+        assert!(pipeline.coverage().unwrap().iter().sum::<u32>() > 0);
+    }
+    #[test]
+    #[should_panic(expected = "does not match the size of the pipeline")]
+    fn test_coverage_size_mismatch() {
+        // the coverage counts are those of a 32x32 image
+        let mut pipeline = Pipeline::new(32, 32, 255.0);
+        pipeline.set_coverage_debug(true);
+        draw(&mut pipeline, &[[Vec2f::new(0, 0), Vec2f::new(64, 0), Vec2f::new(64, 48)]]);
+    }
+}
+
+#[cfg(test)]
+mod test_msaa {
+    use super::super::gl::*;
+    use std::cell::Cell;
+
+    struct CountingShader {
+        calls: Cell<u32>,
+    }
+
+    impl Shader for CountingShader {
+        type Varyings = ();
+        fn vertex(&self, _camera: &Camera, _model: &Model, _iface: i32, _nthvert: i32) -> (Vec4f, ()) {
+            (Vec4f::zero(), ())
+        }
+        fn fragment(&self, _v: &(), color: &mut Color) -> bool {
+            self.calls.set(self.calls.get() + 1);
+            *color = Color::with_color(RGBColor(255, 255, 255));
+            false
+        }
+    }
+
+    fn triangle() -> [Vec4f; 3] {
+        [Vec4f::new([3.3, 2.1, 0.0, 1.0]), Vec4f::new([28.7, 9.6, 0.0, 1.0]), Vec4f::new([11.2, 29.4, 0.0, 1.0])]
+    }
+
+    #[test]
+    fn test_fragment_once_per_pixel() {
+        let shader = CountingShader { calls: Cell::new(0) };
+        let mut pipeline = Pipeline::new(32, 32, 255.0);
+        pipeline.set_coverage_debug(true);
+        let mut buffer = MsaaBuffer::new(32, 32, tga_image::RGB, SampleCount::X4);
+        pipeline.triangle(&triangle(), &[(), (), ()], &shader, &mut buffer);
+        let covered = pipeline.coverage().unwrap().iter().sum::<u32>();
+        assert!(covered > 0);
+        assert_eq!(shader.calls.get(), covered);
+    }
+    #[test]
+    fn test_resolve_edges() {
+        let shader = CountingShader { calls: Cell::new(0) };
+        let mut pipeline = Pipeline::new(32, 32, 255.0);
+        for &filter in [ResolveFilter::Box, ResolveFilter::Tent].iter() {
+            let mut buffer = MsaaBuffer::new(32, 32, tga_image::RGB, SampleCount::X8);
+            pipeline.triangle(&triangle(), &[(), (), ()], &shader, &mut buffer);
+            let mut image = TGAImage::with_info(32, 32, tga_image::RGB);
+            buffer.resolve(&mut image, filter);
+            let mut partial = 0;
+            for y in 0..32 {
+                for x in 0..32 {
+                    let v = image.get(x, y)[0];
+                    if v > 0 && v < 255 {
+                        partial += 1;
+                    }
+                }
+            }
+            assert!(partial > 0);
+            assert_eq!(image.get(0, 31)[0], 0);
+            assert_eq!(image.get(14, 12)[0], 255);
+        }
+    }
+    #[test]
+    fn test_single_sample_matches() {
+        let shader = CountingShader { calls: Cell::new(0) };
+        let mut pipeline = Pipeline::new(32, 32, 255.0);
+        let mut buffer = MsaaBuffer::new(32, 32, tga_image::RGB, SampleCount::X1);
+        pipeline.triangle(&triangle(), &[(), (), ()], &shader, &mut buffer);
+        let mut resolved = TGAImage::with_info(32, 32, tga_image::RGB);
+        buffer.resolve(&mut resolved, ResolveFilter::Box);
+        let mut image = TGAImage::with_info(32, 32, tga_image::RGB);
+        let mut zbuffer = vec![std::f32::MIN; 32 * 32];
+        pipeline.triangle(&triangle(), &[(), (), ()], &shader, Target::new(&mut image, &mut zbuffer));
+        assert_eq!(resolved.buffer(), image.buffer());
+        assert_eq!(buffer.depth(), &zbuffer[..]);
+    }
+}
+
+#[cfg(test)]
+mod test_culling {
+    use super::super::gl::*;
+    use super::common;
+
+    fn render(cull: CullMode, front_face: Winding) -> (TGAImage, RenderStats) {
+        let (camera, model) = (common::camera(), common::head());
+        let (mut pipeline, mut image, mut zbuffer) = common::targets(120, 120);
+        pipeline.cull = cull;
+        pipeline.front_face = front_face;
+        let shader = GourauShader::new();
+        pipeline.draw(&camera, &model, &shader, Target::new(&mut image, &mut zbuffer));
+        (image, pipeline.stats())
+    }
+
+    #[test]
+    fn test_back_faces_are_hidden() {
+        let (mut all, stats) = render(CullMode::None, Winding::Ccw);
+        assert_eq!(stats.culled, 0);
+        let (mut back, stats) = render(CullMode::Back, Winding::Ccw);
+        assert!(stats.culled > 0 && stats.culled < stats.triangles);
+        assert!(all.buffer() == back.buffer());
+    }
+    #[test]
+    fn test_front_and_back_split() {
+        let (_, back) = render(CullMode::Back, Winding::Ccw);
+        let (_, front) = render(CullMode::Front, Winding::Ccw);
+        let (_, cw) = render(CullMode::Front, Winding::Cw);
+        assert_eq!(back.triangles, front.triangles);
+        // faces seen edge-on or off screen are dropped by neither
+        assert!(back.culled + front.culled <= back.triangles);
+        assert_eq!(cw.culled, back.culled);
+    }
+    #[test]
+    fn test_winding() {
+        let shader = GourauShader::new();
+        let ccw = [Vec4f::new([2.0, 2.0, 0.0, 1.0]), Vec4f::new([14.0, 2.0, 0.0, 1.0]), Vec4f::new([2.0, 14.0, 0.0, 1.0])];
+        let cw = [ccw[0], ccw[2], ccw[1]];
+        let mut pipeline = Pipeline::new(16, 16, 255.0);
+        pipeline.cull = CullMode::Back;
+        let mut image = TGAImage::with_info(16, 16, tga_image::RGB);
+        let mut zbuffer = vec![std::f32::MIN; 16 * 16];
+        pipeline.triangle(&ccw, &[0.0; 3], &shader, Target::new(&mut image, &mut zbuffer));
+        assert_eq!(pipeline.stats().culled, 0);
+        pipeline.triangle(&cw, &[0.0; 3], &shader, Target::new(&mut image, &mut zbuffer));
+        assert_eq!(pipeline.stats(), RenderStats { triangles: 2, culled: 1, clipped: 0, hiz_rejected: 0 });
+        pipeline.reset_stats();
+        pipeline.front_face = Winding::Cw;
+        pipeline.triangle(&ccw, &[0.0; 3], &shader, Target::new(&mut image, &mut zbuffer));
+        assert_eq!(pipeline.stats().culled, 1);
+    }
+}
+
+#[cfg(test)]
+mod test_depth {
+    use super::super::gl::*;
+    use super::common;
+
+    fn render(depth: DepthState) -> (TGAImage, Vec<f32>) {
+        let (camera, model) = (common::camera(), common::head());
+        let (mut pipeline, mut image, mut zbuffer) = common::targets(120, 120);
+        pipeline.depth = depth;
+        pipeline.clear_depth(&mut zbuffer);
+        let shader = GourauShader::new();
+        pipeline.draw(&camera, &model, &shader, Target::new(&mut image, &mut zbuffer));
+        (image, zbuffer)
+    }
+
+    #[test]
+    fn test_compare() {
+        assert!(CompareFunc::GEqual.test(1.0, 1.0));
+        assert!(!CompareFunc::Greater.test(1.0, 1.0));
+        assert!(CompareFunc::Less.test(0.5, 1.0));
+        assert!(!CompareFunc::Never.test(0.5, 1.0));
+        assert!(CompareFunc::Always.test(2.0, 1.0));
+        assert!(CompareFunc::NotEqual.test(2, 1));
+    }
+    #[test]
+    fn test_reversed() {
+        let (mut image, zbuffer) = render(DepthState::new(255.0));
+        let (mut reversed, reversed_z) = render(DepthState::reversed(255.0));
+        assert!(image.buffer() == reversed.buffer());
+        for (&z, &r) in zbuffer.iter().zip(reversed_z.iter()) {
+            if z == std::f32::MIN {
+                assert_eq!(r, std::f32::MAX);
+            } else {
+                assert!((255.0 - z - r).abs() < 1e-3);
+            }
+        }
+    }
+    #[test]
+    fn test_write_mask() {
+        let (mut image, zbuffer) = render(DepthState::new(255.0));
+        let (mut read_only, read_only_z) = render(DepthState::new(255.0).read_only());
+        assert!(read_only_z.iter().all(|&z| z == std::f32::MIN));
+        assert!(image.buffer() != read_only.buffer());
+        assert!(zbuffer.iter().any(|&z| z != std::f32::MIN));
+    }
+    #[test]
+    fn test_never() {
+        let mut depth = DepthState::new(255.0);
+        depth.func = CompareFunc::Never;
+        let (mut image, _) = render(depth);
+        assert!(image.buffer().iter().all(|&c| c == 0));
+    }
+}
+
+#[cfg(test)]
+mod test_stencil {
+    use super::super::gl::*;
+
+    fn quad(x0: f32, y0: f32, x1: f32, y1: f32, z: f32) -> [[Vec4f; 3]; 2] {
+        let p = |x: f32, y: f32| Vec4f::new([x, y, z, 1.0]);
+        [[p(x0, y0), p(x1, y0), p(x1, y1)], [p(x0, y0), p(x1, y1), p(x0, y1)]]
+    }
+
+    #[test]
+    fn test_update() {
+        let mut state = StencilState::new();
+        state.reference = 0x0f;
+        state.write_mask = 0x3c;
+        assert_eq!(state.update(StencilOp::Replace, 0xf0), 0xcc);
+        assert_eq!(state.update(StencilOp::Invert, 0x00), 0x3c);
+        state.write_mask = 0xff;
+        assert_eq!(state.update(StencilOp::DecrWrap, 0), 255);
+        assert_eq!(state.update(StencilOp::Decr, 0), 0);
+        assert_eq!(state.update(StencilOp::IncrWrap, 255), 0);
+        assert_eq!(state.update(StencilOp::Incr, 255), 255);
+    }
+    #[test]
+    fn test_mask() {
+        let shader = GourauShader::new();
+        let mut pipeline = Pipeline::new(16, 16, 255.0);
+        let mut image = TGAImage::with_info(16, 16, tga_image::RGB);
+        let mut zbuffer = vec![std::f32::MIN; 16 * 16];
+        let mut stencil = vec![0u8; 16 * 16];
+        // write 1 where the small quad is
+        pipeline.stencil = StencilState::new().both(StencilFace { pass: StencilOp::Replace, ..StencilFace::new() });
+        pipeline.stencil.reference = 1;
+        for t in quad(4.0, 4.0, 8.0, 8.0, 0.0).iter() {
+            pipeline.triangle(t, &[0.0; 3], &shader, Target::new(&mut image, &mut zbuffer).with_stencil(&mut stencil));
+        }
+        // then draw only where the stencil is not 1
+        pipeline.stencil = StencilState::new().both(StencilFace { func: CompareFunc::NotEqual, pass: StencilOp::IncrWrap, ..StencilFace::new() });
+        pipeline.stencil.reference = 1;
+        for t in quad(0.0, 0.0, 16.0, 16.0, 10.0).iter() {
+            pipeline.triangle(t, &[0.0; 3], &shader, Target::new(&mut image, &mut zbuffer).with_stencil(&mut stencil));
+        }
+        for y in 0..16 {
+            for x in 0..16 {
+                let inside = x >= 4 && x < 8 && y >= 4 && y < 8;
+                assert_eq!(stencil[x + y * 16], 1, "pixel ({}, {})", x, y);
+                assert_eq!(zbuffer[x + y * 16], if inside { 0.0 } else { 10.0 });
+            }
+        }
+    }
+    #[test]
+    fn test_shadow_volume_counts() {
+        let shader = GourauShader::new();
+        let mut pipeline = Pipeline::new(16, 16, 255.0);
+        let mut image = TGAImage::with_info(16, 16, tga_image::RGB);
+        let mut zbuffer = vec![std::f32::MIN; 16 * 16];
+        let mut stencil = vec![0u8; 16 * 16];
+        // the receiver, in front of the far half of the volume
+        for t in quad(0.0, 0.0, 16.0, 16.0, 5.0).iter() {
+            pipeline.triangle(t, &[0.0; 3], &shader, Target::new(&mut image, &mut zbuffer).with_stencil(&mut stencil));
+        }
+        pipeline.depth = pipeline.depth.read_only();
+        pipeline.stencil = StencilState::new();
+        pipeline.stencil.enabled = true;
+        pipeline.stencil.front.depth_fail = StencilOp::DecrWrap;
+        pipeline.stencil.back.depth_fail = StencilOp::IncrWrap;
+        // z-fail: back faces behind the receiver count up, front faces behind
+        // it count down
+        let back = quad(0.0, 0.0, 8.0, 16.0, 1.0);
+        let front = quad(0.0, 0.0, 4.0, 16.0, 2.0);
+        for t in back.iter() {
+            pipeline.triangle(&[t[0], t[2], t[1]], &[0.0; 3], &shader, Target::new(&mut image, &mut zbuffer).with_stencil(&mut stencil));
+        }
+        for t in front.iter() {
+            pipeline.triangle(t, &[0.0; 3], &shader, Target::new(&mut image, &mut zbuffer).with_stencil(&mut stencil));
+        }
+        for x in 0..16 {
+            assert_eq!(stencil[x + 8 * 16], if x >= 4 && x < 8 { 1 } else { 0 });
+        }
+        assert!(zbuffer.iter().all(|&z| z == 5.0));
+    }
+}
+
+#[cfg(test)]
+mod test_blend {
+    use super::super::gl::*;
+    use super::common::FlatShader;
+
+    /// Draw a full screen quad of `top` over one of `bottom`, return the
+    /// resulting blue, green, red.
+    fn blend(state: BlendState, bottom: ColorType, top: ColorType) -> [u8; 3] {
+        let mut pipeline = Pipeline::new(4, 4, 255.0);
+        let mut image = TGAImage::with_info(4, 4, tga_image::RGB);
+        let mut zbuffer = vec![std::f32::MIN; 4 * 4];
+        let p = |x: f32, y: f32| Vec4f::new([x, y, 0.0, 1.0]);
+        let quad = [[p(0.0, 0.0), p(4.0, 0.0), p(4.0, 4.0)], [p(0.0, 0.0), p(4.0, 4.0), p(0.0, 4.0)]];
+        for (i, &color) in [bottom, top].iter().enumerate() {
+            pipeline.blend = if i == 0 { BlendState::new() } else { state };
+            for t in quad.iter() {
+                pipeline.triangle(t, &[(), (), ()], &FlatShader { color: color }, Target::new(&mut image, &mut zbuffer));
+            }
+        }
+        let c = image.get(1, 2);
+        [c[0], c[1], c[2]]
+    }
+
+    #[test]
+    fn test_disabled_overwrites() {
+        assert_eq!(blend(BlendState::new(), RGBColor(0, 0, 255), RGBAColor(255, 0, 0, 128)), [0, 0, 255]);
+    }
+    #[test]
+    fn test_alpha() {
+        assert_eq!(blend(BlendState::alpha(), RGBColor(0, 0, 255), RGBAColor(255, 0, 0, 128)), [127, 0, 128]);
+        // no alpha channel means opaque
+        assert_eq!(blend(BlendState::alpha(), RGBColor(0, 0, 255), RGBColor(255, 0, 0)), [0, 0, 255]);
+    }
+    #[test]
+    fn test_equations() {
+        let bottom = RGBColor(100, 200, 50);
+        let top = RGBColor(150, 20, 50);
+        let one = |eq| BlendState::with(BlendFactor::One, BlendFactor::One, eq);
+        assert_eq!(blend(one(BlendEquation::Add), bottom, top), [100, 220, 250]);
+        assert_eq!(blend(one(BlendEquation::Subtract), bottom, top), [0, 0, 50]);
+        assert_eq!(blend(one(BlendEquation::ReverseSubtract), bottom, top), [0, 180, 0]);
+        assert_eq!(blend(one(BlendEquation::Min), bottom, top), [50, 20, 100]);
+        assert_eq!(blend(one(BlendEquation::Max), bottom, top), [50, 200, 150]);
+        let modulate = BlendState::with(BlendFactor::DstColor, BlendFactor::Zero, BlendEquation::Add);
+        assert_eq!(blend(modulate, RGBColor(255, 128, 0), RGBColor(128, 128, 128)), [0, 64, 128]);
+    }
+    #[test]
+    fn test_gray() {
+        // a gray source is the same on every channel, and opaque
+        assert_eq!(blend(BlendState::alpha(), RGBColor(0, 0, 255), GRAY(200)), [200, 200, 200]);
+        let one = BlendState::with(BlendFactor::One, BlendFactor::One, BlendEquation::Add);
+        assert_eq!(blend(one, RGBColor(10, 20, 30), GRAY(100)), [130, 120, 110]);
+    }
+}
+
+#[cfg(test)]
+mod test_primitives {
+    use super::super::gl::*;
+    use super::common;
+    use super::common::FlatShader;
+
+    fn p(x: f32, y: f32, z: f32) -> Vec4f {
+        Vec4f::new([x, y, z, 1.0])
+    }
+
+    #[test]
+    fn test_line_pixels() {
+        let mut pipeline = Pipeline::new(16, 16, 255.0);
+        pipeline.set_coverage_debug(true);
+        let mut image = TGAImage::with_info(16, 16, tga_image::RGB);
+        let mut zbuffer = vec![std::f32::MIN; 16 * 16];
+        let shader = FlatShader { color: RGBColor(255, 255, 255) };
+        pipeline.line(&[p(0.5, 0.5, 0.0), p(12.5, 6.5, 0.0)], &[(), ()], &shader, Target::new(&mut image, &mut zbuffer));
+        let coverage = pipeline.coverage().unwrap();
+        // one pixel per column, the last end left out
+        for x in 0..16 {
+            let n: u32 = (0..16).map(|y| coverage[x + y * 16]).sum();
+            assert_eq!(n, if x < 12 { 1 } else { 0 }, "column {}", x);
+        }
+        assert_eq!(coverage[0], 1);
+        assert_eq!(coverage[11 + 6 * 16], 1);
+    }
+    #[test]
+    fn test_line_depth() {
+        let mut pipeline = Pipeline::new(16, 16, 255.0);
+        let mut image = TGAImage::with_info(16, 16, tga_image::RGB);
+        let mut zbuffer = vec![std::f32::MIN; 16 * 16];
+        let quad = [[p(0.0, 0.0, 10.0), p(8.0, 0.0, 10.0), p(8.0, 16.0, 10.0)], [p(0.0, 0.0, 10.0), p(8.0, 16.0, 10.0), p(0.0, 16.0, 10.0)]];
+        for t in quad.iter() {
+            pipeline.triangle(t, &[(), (), ()], &FlatShader { color: RGBColor(0, 0, 255) }, Target::new(&mut image, &mut zbuffer));
+        }
+        pipeline.line(&[p(0.0, 8.5, 5.0), p(16.0, 8.5, 5.0)], &[(), ()], &FlatShader { color: RGBColor(255, 0, 0) }, Target::new(&mut image, &mut zbuffer));
+        // hidden behind the quad, visible past it
+        assert_eq!(image.get(3, 8)[2], 0);
+        assert_eq!(image.get(12, 8)[2], 255);
+    }
+    #[test]
+    fn test_point_size() {
+        let mut pipeline = Pipeline::new(16, 16, 255.0);
+        pipeline.set_coverage_debug(true);
+        pipeline.point_size = 3.0;
+        let mut image = TGAImage::with_info(16, 16, tga_image::RGB);
+        let mut zbuffer = vec![std::f32::MIN; 16 * 16];
+        pipeline.point(p(5.5, 7.5, 0.0), &(), &FlatShader { color: RGBColor(255, 255, 255) }, Target::new(&mut image, &mut zbuffer));
+        let coverage = pipeline.coverage().unwrap();
+        assert_eq!(coverage.iter().sum::<u32>(), 9);
+        assert_eq!(coverage[4 + 6 * 16], 1);
+        assert_eq!(coverage[6 + 8 * 16], 1);
+    }
+    #[test]
+    fn test_wireframe_overlay() {
+        let (camera, model) = (common::camera(), common::head());
+        let wire = |bias: f32, threads: usize| {
+            let (mut pipeline, mut image, mut zbuffer) = common::targets(120, 120);
+            pipeline.threads = threads;
+            pipeline.draw(&camera, &model, &FlatShader { color: RGBColor(0, 0, 255) }, Target::new(&mut image, &mut zbuffer));
+            pipeline.polygon_mode = PolygonMode::Line;
+            pipeline.cull = CullMode::Back;
+            pipeline.depth_bias = DepthBias::with(bias, 0.0);
+            pipeline.draw(&camera, &model, &FlatShader { color: RGBColor(255, 0, 0) }, Target::new(&mut image, &mut zbuffer));
+            let mut red = 0;
+            for y in 0..120 {
+                for x in 0..120 {
+                    if image.get(x, y)[2] == 255 {
+                        red += 1;
+                    }
+                }
+            }
+            (red, image)
+        };
+        let (fighting, _) = wire(0.0, 1);
+        let (biased, mut serial) = wire(0.5, 1);
+        let (_, mut tiled) = wire(0.5, 4);
+        assert!(biased > fighting);
+        assert!(serial.buffer() == tiled.buffer());
+    }
+}
+
+#[cfg(test)]
+mod test_line_aa {
+    use super::super::gl::*;
+
+    fn image() -> TGAImage {
+        TGAImage::with_info(16, 16, tga_image::GRAYSCALE)
+    }
+
+    #[test]
+    fn test_on_pixel_centres() {
+        let mut image = image();
+        line_aa(Vec2f::new(1.5, 4.5), Vec2f::new(10.5, 4.5), &mut image, Color::grayscale(255), &LineStyle::new());
+        for x in 2..10 {
+            assert_eq!(image.get(x, 4)[0], 255);
+            assert_eq!(image.get(x, 3)[0], 0);
+            assert_eq!(image.get(x, 5)[0], 0);
+        }
+    }
+    #[test]
+    fn test_between_pixels() {
+        let mut image = image();
+        line_aa(Vec2f::new(1.5, 5.0), Vec2f::new(10.5, 5.0), &mut image, Color::grayscale(255), &LineStyle::new());
+        for x in 2..10 {
+            assert_eq!(image.get(x, 4)[0], 128);
+            assert_eq!(image.get(x, 5)[0], 128);
+        }
+    }
+    #[test]
+    fn test_steep_is_smooth() {
+        let mut image = image();
+        line_aa(Vec2f::new(3.2, 1.0), Vec2f::new(7.9, 14.0), &mut image, Color::grayscale(255), &LineStyle::new());
+        let partial = (0..16 * 16).filter(|&i| { let v = image.get(i % 16, i / 16)[0]; v > 0 && v < 255 }).count();
+        assert!(partial > 10);
+        // every row along the line gets about one pixel worth of ink
+        for y in 2..13 {
+            let ink: u32 = (0..16).map(|x| image.get(x, y)[0] as u32).sum();
+            assert!(ink >= 250 && ink <= 260, "row {}: {}", y, ink);
+        }
+    }
+    #[test]
+    fn test_width_and_caps() {
+        let draw = |cap| {
+            let mut image = image();
+            line_aa(Vec2f::new(4.0, 8.0), Vec2f::new(12.0, 8.0), &mut image, Color::grayscale(255), &LineStyle::with(4.0, cap));
+            image
+        };
+        let butt = draw(LineCap::Butt);
+        let square = draw(LineCap::Square);
+        let round = draw(LineCap::Round);
+        for &(x, y) in [(8, 6), (8, 9), (5, 7)].iter() {
+            assert_eq!(butt.get(x, y)[0], 255);
+        }
+        assert_eq!(butt.get(8, 10)[0], 0);
+        assert_eq!(butt.get(2, 8)[0], 0);
+        assert_eq!(square.get(2, 8)[0], 255);
+        assert_eq!(round.get(3, 8)[0], 255);
+        assert_eq!(square.get(2, 6)[0], 255);
+        assert!(round.get(2, 6)[0] < 255);
+    }
+    #[test]
+    fn test_blends() {
+        let mut image = TGAImage::with_info(16, 16, tga_image::RGB);
+        for x in 0..16 {
+            for y in 0..16 {
+                image.set(x, y, Color::with_color(RGBColor(0, 0, 200)));
+            }
+        }
+        line_aa(Vec2f::new(0.5, 8.5), Vec2f::new(15.5, 8.5), &mut image, Color::with_color(RGBAColor(200, 0, 0, 128)), &LineStyle::new());
+        let c = image.get(8, 8);
+        assert_eq!((c[2], c[1], c[0]), (100, 0, 100));
+        // off-screen ends are fine
+        line_aa(Vec2f::new(-10.0, -3.0), Vec2f::new(30.0, 20.0), &mut image, Color::with_color(RGBColor(255, 255, 255)), &LineStyle::with(3.0, LineCap::Round));
+    }
+    #[test]
+    fn test_depth() {
+        // nothing drawn on the left, a wall at depth 100 from x 8
+        let mut zbuffer = vec![std::f32::MIN; 16 * 16];
+        for y in 0..16 {
+            for x in 8..16 {
+                zbuffer[x + y * 16] = 100.0;
+            }
+        }
+        let mut image = image();
+        let white = Color::grayscale(255);
+        // behind the wall all along
+        line_aa_depth(Vec3f::new(1.5, 4.5, 50.0), Vec3f::new(14.5, 4.5, 50.0), &mut image, &zbuffer, CompareFunc::Greater, white, &LineStyle::new());
+        assert_eq!(image.get(3, 4)[0], 255);
+        assert_eq!(image.get(7, 4)[0], 255);
+        assert_eq!(image.get(8, 4)[0], 0);
+        assert_eq!(image.get(12, 4)[0], 0);
+        // coming out of it on the way
+        line_aa_depth(Vec3f::new(1.5, 10.5, 0.0), Vec3f::new(14.5, 10.5, 130.0), &mut image, &zbuffer, CompareFunc::Greater, white, &LineStyle::with(2.0, LineCap::Butt));
+        assert_eq!(image.get(3, 10)[0], 255);
+        assert_eq!(image.get(8, 10)[0], 0);
+        assert_eq!(image.get(12, 10)[0], 255);
+    }
+}
+
+#[cfg(test)]
+mod test_scissor {
+    use super::super::gl::*;
+
+    fn draw(pipeline: &mut Pipeline, pts: &[Vec4f]) -> Vec<u32> {
+        pipeline.set_coverage_debug(true);
+        let mut image = TGAImage::with_info(32, 24, tga_image::RGB);
+        let mut zbuffer = vec![std::f32::MIN; 32 * 24];
+        pipeline.triangle(pts, &[0.0; 3], &GourauShader::new(), Target::new(&mut image, &mut zbuffer));
+        pipeline.coverage().unwrap().to_vec()
+    }
+
+    fn p(x: f32, y: f32) -> Vec4f {
+        Vec4f::new([x, y, 0.0, 1.0])
+    }
+
+    #[test]
+    fn test_scissor() {
+        let mut pipeline = Pipeline::new(32, 24, 255.0);
+        pipeline.scissor = Some(Rect::new(4, 2, 10, 20));
+        let coverage = draw(&mut pipeline, &[p(0.0, 0.0), p(64.0, 0.0), p(0.0, 48.0)]);
+        for y in 0..24 {
+            for x in 0..32 {
+                let inside = x >= 4 && x < 10 && y >= 2 && y < 20;
+                assert_eq!(coverage[x + y * 32], if inside { 1 } else { 0 });
+            }
+        }
+    }
+    #[test]
+    fn test_guard_band() {
+        // overhangs the screen on three sides but stays in the guard band
+        let pts = [p(-20.0, -10.0), p(60.0, 5.0), p(10.0, 40.0)];
+        let mut pipeline = Pipeline::new(32, 24, 255.0);
+        let guarded = draw(&mut pipeline, &pts);
+        assert_eq!(pipeline.stats().clipped, 0);
+        let mut pipeline = Pipeline::new(32, 24, 255.0);
+        pipeline.frustum = Frustum::new(32, 24);
+        let clipped = draw(&mut pipeline, &pts);
+        assert_eq!(pipeline.stats().clipped, 1);
+        assert_eq!(guarded, clipped);
+        assert!(guarded.iter().all(|&n| n <= 1));
+    }
+    #[test]
+    fn test_huge_triangle() {
+        let mut pipeline = Pipeline::new(32, 24, 255.0);
+        let coverage = draw(&mut pipeline, &[p(-1.0e6, -1.0e6), p(1.0e6, -1.0e6), p(0.0, 1.0e6)]);
+        assert_eq!(pipeline.stats().clipped, 1);
+        assert!(coverage.iter().all(|&n| n == 1));
+    }
+}
+
+#[cfg(test)]
+mod test_depth_bias {
+    use super::super::gl::*;
+
+    fn draw(bias: DepthBias) -> Vec<f32> {
+        // z goes from 0 to 48 across the 16 pixels: a slope of 3
+        let p = |x: f32, y: f32| Vec4f::new([x, y, x * 3.0, 1.0]);
+        let mut pipeline = Pipeline::new(16, 16, 255.0);
+        pipeline.depth_bias = bias;
+        let mut image = TGAImage::with_info(16, 16, tga_image::RGB);
+        let mut zbuffer = vec![std::f32::MIN; 16 * 16];
+        pipeline.triangle(&[p(0.0, 0.0), p(16.0, 0.0), p(16.0, 16.0)], &[0.0; 3], &GourauShader::new(), Target::new(&mut image, &mut zbuffer));
+        zbuffer
+    }
+
+    #[test]
+    fn test_constant_and_slope() {
+        let none = draw(DepthBias::new());
+        let constant = draw(DepthBias::with(2.0, 0.0));
+        let sloped = draw(DepthBias::with(-1.0, 0.5));
+        for i in 0..16 * 16 {
+            if none[i] == std::f32::MIN {
+                assert_eq!(constant[i], std::f32::MIN);
+                continue;
+            }
+            assert!((constant[i] - none[i] - 2.0).abs() < 1e-3);
+            assert!((sloped[i] - none[i] - 0.5).abs() < 1e-3);
+        }
+    }
+    #[test]
+    fn test_per_draw() {
+        // the same triangle drawn again only passes GEqual if biased forward
+        let p = |x: f32, y: f32| Vec4f::new([x, y, x * 3.0 + y, 1.0]);
+        let pts = [p(0.0, 0.0), p(16.0, 0.0), p(16.0, 16.0)];
+        let mut pipeline = Pipeline::new(16, 16, 255.0);
+        pipeline.set_coverage_debug(true);
+        pipeline.depth.func = CompareFunc::Greater;
+        let mut image = TGAImage::with_info(16, 16, tga_image::RGB);
+        let mut zbuffer = vec![std::f32::MIN; 16 * 16];
+        pipeline.triangle(&pts, &[0.0; 3], &GourauShader::new(), Target::new(&mut image, &mut zbuffer));
+        let first = zbuffer.clone();
+        pipeline.triangle(&pts, &[0.0; 3], &GourauShader::new(), Target::new(&mut image, &mut zbuffer));
+        assert!(first == zbuffer);
+        pipeline.depth_bias = DepthBias::with(0.0, 0.1);
+        pipeline.triangle(&pts, &[0.0; 3], &GourauShader::new(), Target::new(&mut image, &mut zbuffer));
+        assert!(first.iter().zip(zbuffer.iter()).all(|(&a, &b)| a == std::f32::MIN || b > a));
+    }
+}
+
+#[cfg(test)]
+mod test_hiz {
+    use super::super::gl::*;
+    use super::common;
+    use std::sync::Arc;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    /// Gouraud shading that counts its fragments.
+    #[derive(Clone)]
+    struct Counting {
+        inner: GourauShader,
+        early: bool,
+        calls: Arc<AtomicUsize>,
+    }
+
+    impl Shader for Counting {
+        type Varyings = f32;
+        fn vertex(&self, camera: &Camera, model: &Model, iface: i32, nthvert: i32) -> (Vec4f, f32) {
+            self.inner.vertex(camera, model, iface, nthvert)
+        }
+        fn fragment(&self, intensity: &f32, color: &mut Color) -> bool {
+            self.calls.fetch_add(1, Ordering::Relaxed);
+            self.inner.fragment(intensity, color)
+        }
+        fn early_z(&self) -> bool { self.early }
+    }
+
+    /// Draw the head twice, the second time everything is hidden by the first.
+    fn render(hiz: bool, early: bool, threads: usize) -> (TGAImage, Vec<f32>, usize, RenderStats) {
+        let camera = common::look(common::eye(), 20, 10, 180);
+        let model = common::head();
+        let (mut pipeline, mut image, mut zbuffer) = common::targets(200, 170);
+        pipeline.threads = threads;
+        pipeline.hierarchical_z = hiz;
+        let calls = Arc::new(AtomicUsize::new(0));
+        let shader = Counting { inner: GourauShader::new(), early: early, calls: calls.clone() };
+        pipeline.draw(&camera, &model, &shader, Target::new(&mut image, &mut zbuffer));
+        pipeline.draw(&camera, &model, &shader, Target::new(&mut image, &mut zbuffer));
+        let stats = pipeline.stats();
+        (image, zbuffer, calls.load(Ordering::Relaxed), stats)
+    }
+
+    #[test]
+    fn test_same_image() {
+        let (mut reference, reference_z, _, stats) = render(false, true, 1);
+        assert_eq!(stats.hiz_rejected, 0);
+        for &threads in [1, 3].iter() {
+            let (mut image, zbuffer, _, stats) = render(true, true, threads);
+            assert!(reference.buffer() == image.buffer());
+            assert!(reference_z == zbuffer);
+            assert!(stats.hiz_rejected > 0);
+        }
+    }
+    #[test]
+    fn test_fewer_fragments() {
+        let (_, _, late, _) = render(false, false, 1);
+        let (_, _, early, _) = render(false, true, 1);
+        let (_, _, hiz, _) = render(true, true, 1);
+        assert!(early < late);
+        assert!(hiz <= early);
+    }
+}
+
+#[cfg(test)]
+mod test_framebuffer {
+    use super::super::gl::*;
+    use super::common;
+    use super::common::FlatShader;
+
+    #[test]
+    fn test_same_as_image() {
+        let (camera, model) = (common::camera(), common::head());
+        let (mut pipeline, _, mut zbuffer) = common::targets(120, 120);
+        let mut image = TGAImage::with_info(120, 120, tga_image::RGBA);
+        pipeline.draw(&camera, &model, &GourauShader::new(), Target::new(&mut image, &mut zbuffer));
+        for &threads in [1, 4].iter() {
+            pipeline.threads = threads;
+            let mut framebuffer = Framebuffer::new(120, 120).with_color(Format::Rgba8).with_color(Format::R32F).with_color(Format::Rgba32F);
+            pipeline.draw(&camera, &model, &GourauShader::new(), &mut framebuffer);
+            assert!(framebuffer.depth() == &zbuffer[..]);
+            assert!(framebuffer.color(0).bytes().unwrap() == &image.buffer()[..]);
+            for y in 0..120 {
+                for x in 0..120 {
+                    let expected = framebuffer.color(0).get(x, y);
+                    assert_eq!(framebuffer.color(2).get(x, y), expected);
+                    assert_eq!(framebuffer.color(1).get(x, y)[0], expected[2]);
+                }
+            }
+        }
+    }
+    #[test]
+    fn test_rgb_is_opaque() {
+        let mut pipeline = Pipeline::new(4, 4, 255.0);
+        let mut framebuffer = Framebuffer::new(4, 4).with_color(Format::Rgba8).with_color(Format::Rgba32F);
+        let pts = [Vec4f::new([0.0, 0.0, 0.0, 1.0]), Vec4f::new([8.0, 0.0, 0.0, 1.0]), Vec4f::new([0.0, 8.0, 0.0, 1.0])];
+        pipeline.triangle(&pts, &[(); 3], &FlatShader { color: RGBColor(10, 20, 30) }, &mut framebuffer);
+        assert_eq!(framebuffer.color(0).get(1, 1)[3], 1.0);
+        assert_eq!(framebuffer.color(1).get(1, 1)[3], 1.0);
+        assert_eq!(framebuffer.color(0).bytes().unwrap()[4..8], [30, 20, 10, 255]);
+    }
+    #[test]
+    fn test_clear() {
+        let mut framebuffer = Framebuffer::new(4, 2).with_stencil().with_color(Format::Rgba8).with_color(Format::R32F);
+        framebuffer.clear_color([0.0, 0.5, 1.0, 1.0]);
+        framebuffer.clear_depth(std::f32::MAX);
+        framebuffer.clear_stencil(3);
+        assert_eq!(framebuffer.color(0).bytes().unwrap()[..4], [0, 128, 255, 255]);
+        assert!(framebuffer.color(1).floats().unwrap().iter().all(|&v| v == 1.0));
+        assert!(framebuffer.depth().iter().all(|&z| z == std::f32::MAX));
+        assert!(framebuffer.stencil().unwrap().iter().all(|&s| s == 3));
+        let image = framebuffer.color(1).to_image();
+        assert_eq!(image.get_bytespp(), tga_image::GRAYSCALE as i32);
+    }
+    #[test]
+    #[should_panic]
+    fn test_size_mismatch() {
+        let mut framebuffer = Framebuffer::new(4, 4);
+        framebuffer.attach(Attachment::new(4, 3, Format::Rgba8));
+    }
+    #[test]
+    #[should_panic]
+    fn test_too_many_attachments() {
+        let mut framebuffer = Framebuffer::new(4, 4);
+        for _ in 0..MAX_OUTPUTS + 1 {
+            framebuffer.attach(Attachment::new(4, 4, Format::R32F));
+        }
+    }
+    #[test]
+    fn test_outputs_past_the_last() {
+        let mut out = FragmentOutputs::new();
+        out.set(MAX_OUTPUTS - 1, [1.0; 4]);
+        assert_eq!(out.get(MAX_OUTPUTS - 1), Some([1.0; 4]));
+        assert_eq!(out.get(MAX_OUTPUTS), None);
+    }
+    #[test]
+    #[should_panic]
+    fn test_zbuffer_mismatch() {
+        let mut pipeline = Pipeline::new(4, 4, 255.0);
+        let mut image = TGAImage::with_info(4, 4, tga_image::RGB);
+        let mut zbuffer = vec![std::f32::MIN; 4 * 3];
+        let pts = [Vec4f::new([0.0, 0.0, 0.0, 1.0]), Vec4f::new([4.0, 0.0, 0.0, 1.0]), Vec4f::new([0.0, 4.0, 0.0, 1.0])];
+        pipeline.triangle(&pts, &[0.0; 3], &GourauShader::new(), Target::new(&mut image, &mut zbuffer));
+    }
+}
+
+#[cfg(test)]
+mod test_deferred {
+    use super::super::gl::*;
+    use super::common;
+
+    /// Writes a colour to the first attachment and its varying to the
+    /// second.
+    #[derive(Clone)]
+    struct TwoOutputs;
+
+    impl Shader for TwoOutputs {
+        type Varyings = f32;
+        fn vertex(&self, _camera: &Camera, _model: &Model, _iface: i32, _nthvert: i32) -> (Vec4f, f32) {
+            (Vec4f::zero(), 0.0)
+        }
+        fn fragment(&self, _v: &f32, _color: &mut Color) -> bool {
+            true
+        }
+        fn fragment_outputs(&self, v: &f32, out: &mut FragmentOutputs) -> bool {
+            out.set(0, [1.0, 0.0, 0.0, 1.0]);
+            out.set_scalar(1, *v);
+            false
+        }
+    }
+
+    #[test]
+    fn test_outputs() {
+        let mut pipeline = Pipeline::new(8, 8, 255.0);
+        let mut framebuffer = Framebuffer::new(8, 8).with_color(Format::Rgba8).with_color(Format::R32F).with_color(Format::Rgba8);
+        framebuffer.color_mut(2).clear([0.0, 1.0, 0.0, 1.0]);
+        let pts = [Vec4f::new([0.0, 0.0, 0.0, 1.0]), Vec4f::new([16.0, 0.0, 0.0, 1.0]), Vec4f::new([0.0, 16.0, 0.0, 1.0])];
+        pipeline.triangle(&pts, &[1.0, 0.0, 0.0], &TwoOutputs, &mut framebuffer);
+        assert_eq!(framebuffer.color(0).get(3, 3), [1.0, 0.0, 0.0, 1.0]);
+        // the first weight falls from 1 at the origin
+        let w = framebuffer.color(1).get(3, 3)[0];
+        assert!((w - (1.0 - 7.0 / 16.0)).abs() < 1.0e-4, "{}", w);
+        // no output, left alone
+        assert_eq!(framebuffer.color(2).get(3, 3), [0.0, 1.0, 0.0, 1.0]);
+    }
+
+    fn geometry(threads: usize) -> GBuffer {
+        let (camera, model) = (common::camera(), common::textured_head());
+        let mut pipeline = Pipeline::new(120, 120, 255.0);
+        pipeline.threads = threads;
+        let mut gbuffer = GBuffer::new(120, 120);
+        pipeline.draw(&camera, &model, &GBufferShader::new(&model), gbuffer.framebuffer_mut());
+        gbuffer
+    }
+
+    #[test]
+    fn test_lighting() {
+        let gbuffer = geometry(1);
+        assert!(!gbuffer.covered(0, 0));
+        assert!(gbuffer.covered(60, 60));
+        assert!((gbuffer.normal(60, 60).norm() - 1.0).abs() < 1.0e-3);
+
+        let eye = common::eye();
+        let key = [Light::directional(Vec3f::new(1, 1, 1), Vec3f::new(1, 1, 1))];
+        let both = [key[0], Light::point(Vec3f::new(0, 0, 2), Vec3f::new(1, 0, 0), 3.0)];
+        let mut pipeline = Pipeline::new(120, 120, 255.0);
+        let mut lit = Attachment::new(120, 120, Format::Rgba32F);
+        pipeline.full_screen(&DeferredLighting::new(&gbuffer, &key, eye), &mut lit);
+        pipeline.threads = 3;
+        let mut brighter = Attachment::new(120, 120, Format::Rgba32F);
+        pipeline.full_screen(&DeferredLighting::new(&gbuffer, &both, eye), &mut brighter);
+
+        assert_eq!(lit.get(0, 0), [0.0; 4]);
+        assert!(lit.get(60, 60)[3] == 1.0);
+        let mut redder = 0;
+        for y in 0..120 {
+            for x in 0..120 {
+                let (a, b) = (lit.get(x, y), brighter.get(x, y));
+                assert!(b[0] == a[0] && b[1] == a[1] && b[2] >= a[2]);
+                if b[2] > a[2] {
+                    redder += 1;
+                }
+            }
+        }
+        assert!(redder > 0);
+    }
+    #[test]
+    fn test_threads() {
+        let serial = geometry(1);
+        let tiled = geometry(4);
+        for i in 0..4 {
+            assert!(serial.framebuffer().color(i).floats() == tiled.framebuffer().color(i).floats());
+            assert!(serial.framebuffer().color(i).bytes() == tiled.framebuffer().color(i).bytes());
+        }
+    }
+}
+
+#[cfg(test)]
+mod test_hdr {
+    use super::super::gl::*;
+
+    #[derive(Clone)]
+    struct Bright {
+        color: ColorF,
+    }
+
+    impl Shader for Bright {
+        type Varyings = ();
+        fn vertex(&self, _camera: &Camera, _model: &Model, _iface: i32, _nthvert: i32) -> (Vec4f, ()) {
+            (Vec4f::zero(), ())
+        }
+        fn fragment(&self, _v: &(), _color: &mut Color) -> bool {
+            true
+        }
+        fn fragment_outputs(&self, _v: &(), out: &mut FragmentOutputs) -> bool {
+            out.broadcast_hdr(self.color);
+            false
+        }
+    }
+
+    #[test]
+    fn test_additive_unclamped() {
+        let mut pipeline = Pipeline::new(4, 4, 255.0);
+        pipeline.depth = DepthState::new(255.0).read_only();
+        pipeline.blend = BlendState::with(BlendFactor::One, BlendFactor::One, BlendEquation::Add);
+        let mut framebuffer = Framebuffer::new(4, 4).with_color(Format::Rgba32F).with_color(Format::Rgba8);
+        let pts = [Vec4f::new([0.0, 0.0, 0.0, 1.0]), Vec4f::new([8.0, 0.0, 0.0, 1.0]), Vec4f::new([0.0, 8.0, 0.0, 1.0])];
+        let shader = Bright { color: ColorF::new(0.8, 2.0, 0.25) };
+        pipeline.triangle(&pts, &[(), (), ()], &shader, &mut framebuffer);
+        pipeline.triangle(&pts, &[(), (), ()], &shader, &mut framebuffer);
+        let hdr = ColorF::from_bgra(framebuffer.color(0).get(1, 1));
+        assert_eq!((hdr.r, hdr.g, hdr.b), (1.6, 4.0, 0.5));
+        // clamped and quantized after each draw
+        let i = (1 + 1 * 4) * 4;
+        assert_eq!(framebuffer.color(1).bytes().unwrap()[i..i + 3], [128, 255, 255]);
+    }
+    #[test]
+    fn test_operators() {
+        assert_eq!(ToneOperator::Reinhard.apply(1.0), 0.5);
+        assert_eq!(ToneOperator::Clamp.apply(3.0), 1.0);
+        assert_eq!(ToneOperator::Clamp.apply(-1.0), 0.0);
+        let mut last = 0.0;
+        for i in 1..100 {
+            let v = ToneOperator::AcesFilmic.apply(i as f32 * 0.25);
+            assert!(v >= last && v <= 1.0);
+            last = v;
+        }
+        assert!(last > 0.95);
+        let mapping = ToneMapping::new(ToneOperator::Reinhard).stops(1.0);
+        assert_eq!(mapping.map(ColorF::new(0.5, 1.5, 0.0)), ColorF::new(0.5, 0.75, 0.0));
+    }
+    #[test]
+    fn test_resolve() {
+        let mut hdr = Attachment::new(2, 1, Format::Rgba32F);
+        hdr.set(0, 0, ColorF::new(1.0, 3.0, 0.0).bgra());
+        hdr.set(1, 0, ColorF::new(100.0, 0.0, 0.0).bgra());
+        let mut image = TGAImage::with_info(2, 1, tga_image::RGB);
+        ToneMapping::new(ToneOperator::Reinhard).resolve(&hdr, &mut image);
+        assert_eq!(image.get(0, 0).raw()[..3], [0, 191, 128]);
+        assert_eq!(image.get(1, 0).raw()[..3], [0, 0, 252]);
+    }
+    #[test]
+    fn test_files() {
+        let mut hdr = Attachment::new(3, 2, Format::Rgba32F);
+        hdr.set(2, 0, ColorF::new(6.0, 0.5, 0.0).bgra());
+        let dir = std::env::temp_dir();
+        let pfm = dir.join("tinyrenderer_test.pfm");
+        hdr.write_pfm(pfm.to_str().unwrap()).unwrap();
+        let data = std::fs::read(&pfm).unwrap();
+        let header = b"PF\n3 2\n-1.0\n";
+        assert_eq!(&data[..header.len()], &header[..]);
+        assert_eq!(data.len(), header.len() + 3 * 2 * 3 * 4);
+        let red = header.len() + 2 * 3 * 4;
+        assert_eq!(data[red..red + 4], 6.0f32.to_le_bytes());
+
+        let radiance = dir.join("tinyrenderer_test.hdr");
+        hdr.write_hdr(radiance.to_str().unwrap()).unwrap();
+        let data = std::fs::read(&radiance).unwrap();
+        let header = b"#?RADIANCE\nFORMAT=32-bit_rle_rgbe\n\n-Y 2 +X 3\n";
+        assert_eq!(&data[..header.len()], &header[..]);
+        assert_eq!(data.len(), header.len() + 3 * 2 * 4);
+        // bottom row last, 6 = 0.75 * 2^3
+        let px = &data[header.len() + (3 + 2) * 4..];
+        assert_eq!(px, &[192, 16, 0, 131]);
+        std::fs::remove_file(pfm).unwrap();
+        std::fs::remove_file(radiance).unwrap();
+    }
+}
+
+#[cfg(test)]
+mod test_postprocess {
+    use super::super::gl::*;
+
+    /// A `w` x `h` image of `color` at a constant depth of 100.
+    fn flat(w: i32, h: i32, format: Format, color: [f32; 4]) -> (Attachment, Vec<f32>) {
+        let mut attachment = Attachment::new(w, h, format);
+        attachment.clear(color);
+        (attachment, vec![100.0; (w * h) as usize])
+    }
+
+    #[test]
+    fn test_flat_unchanged() {
+        let (color, depth) = flat(16, 16, Format::Rgba8, [0.2, 0.4, 0.6, 1.0]);
+        let chain = PostChain::new().then(Ssao::new()).then(Fxaa::new()).then(Outline::new()).then(Bloom::new());
+        let out = chain.run(&color, &depth, &DepthState::new(255.0));
+        assert!(out.bytes() == color.bytes());
+    }
+    #[test]
+    fn test_gamma() {
+        assert!((Gamma::Srgb.encode(0.5) - 0.7354).abs() < 1.0e-4);
+        assert!((Gamma::Srgb.encode(0.001) - 0.01292).abs() < 1.0e-6);
+        assert_eq!(Gamma::Power(2.0).encode(0.25), 0.5);
+        let (color, depth) = flat(2, 2, Format::Rgba32F, [0.25, 1.0, 4.0, 0.5]);
+        let out = PostChain::new().then(Gamma::Power(2.0)).run(&color, &depth, &DepthState::new(255.0));
+        assert_eq!(out.get(1, 1), [0.5, 1.0, 2.0, 0.5]);
+    }
+    #[test]
+    fn test_outline() {
+        let (color, mut depth) = flat(12, 12, Format::Rgba8, [1.0; 4]);
+        // a closer square from 4 to 8, nothing drawn in the last column
+        for y in 4..8 {
+            for x in 4..8 {
+                depth[x + y * 12] = 200.0;
+            }
+        }
+        for y in 0..12 {
+            depth[11 + y * 12] = std::f32::MIN;
+        }
+        let out = PostChain::new().then(Outline::new()).run(&color, &depth, &DepthState::new(255.0));
+        let black = |x, y| out.get(x, y)[..3] == [0.0, 0.0, 0.0];
+        assert!(black(4, 4) && black(7, 5) && black(3, 5) && black(8, 6) && black(10, 0));
+        assert!(!black(5, 5) && !black(1, 1) && !black(11, 3));
+    }
+    #[test]
+    fn test_bloom() {
+        let (mut color, depth) = flat(15, 15, Format::Rgba32F, [0.1, 0.1, 0.1, 1.0]);
+        color.set(7, 7, [8.0, 8.0, 8.0, 1.0]);
+        let out = PostChain::new().then(Bloom::new()).run(&color, &depth, &DepthState::new(255.0));
+        assert!(out.get(7, 7)[0] > 8.0);
+        assert!(out.get(9, 7)[0] > 0.1);
+        assert_eq!(out.get(9, 7), out.get(5, 7));
+        assert_eq!(out.get(7, 9), out.get(9, 7));
+    }
+    #[test]
+    fn test_fxaa() {
+        // a black and white staircase
+        let (mut color, depth) = flat(16, 16, Format::Rgba8, [0.0, 0.0, 0.0, 1.0]);
+        for y in 0..16 {
+            for x in 0..16 {
+                if x > y / 4 + 6 {
+                    color.set(x, y, [1.0; 4]);
+                }
+            }
+        }
+        let out = PostChain::new().then(Fxaa::new()).run(&color, &depth, &DepthState::new(255.0));
+        let grays = (0..256).filter(|i| { let v = out.get(i % 16, i / 16)[0]; v > 0.0 && v < 1.0 }).count();
+        assert!(grays > 0);
+        // away from the edge
+        assert_eq!(out.get(1, 8)[0], 0.0);
+        assert_eq!(out.get(14, 8)[0], 1.0);
+        let again = PostChain::new().then(Fxaa::new()).run(&color, &depth, &DepthState::new(255.0));
+        assert!(again.bytes() == out.bytes());
+    }
+}
+
+#[cfg(test)]
+mod test_ssao {
+    use super::super::gl::*;
+
+    /// A floor at depth 100 with a wall 4 high at x 4 to 8 and a slope
+    /// rising from x 16, one depth unit to the pixel.
+    fn scene() -> (Vec<f32>, Ssao) {
+        let mut depth = vec![100.0; 24 * 24];
+        for y in 0..24 {
+            for x in 4..8 {
+                depth[x + y * 24] = 104.0;
+            }
+            for x in 16..24 {
+                depth[x + y * 24] = 100.0 + (x - 16) as f32 * 1.5;
+            }
+        }
+        let mut ssao = Ssao::new();
+        ssao.depth_scale = Some(255.0);
+        ssao.radius = 4.0;
+        ssao.bias = 0.5;
+        ssao.blur = 0;
+        (depth, ssao)
+    }
+
+    /// Occluded at the foot of the wall only, whatever the depth convention.
+    fn check(depth: &[f32], ssao: &Ssao) {
+        let ao = ssao.occlusion(depth, 24, 24, &DepthState::new(255.0));
+        assert_eq!(ao.format(), Format::R32F);
+        // at the foot of the wall, on top of it, on the open floor, on the slope
+        assert!(ao.get(3, 10)[0] < 0.9, "{}", ao.get(3, 10)[0]);
+        assert_eq!(ao.get(5, 10)[0], 1.0);
+        assert_eq!(ao.get(12, 10)[0], 1.0);
+        assert!(ao.get(21, 10)[0] > 0.9, "{}", ao.get(21, 10)[0]);
+        let reversed: Vec<f32> = depth.iter().map(|&z| 255.0 - z).collect();
+        let again = ssao.occlusion(&reversed, 24, 24, &DepthState::reversed(255.0));
+        assert!(again.floats() == ao.floats());
+    }
+
+    #[test]
+    fn test_hemisphere() {
+        let (depth, ssao) = scene();
+        check(&depth, &ssao);
+    }
+    #[test]
+    fn test_horizon() {
+        let (depth, ssao) = scene();
+        check(&depth, &ssao.with_method(AoMethod::Horizon));
+    }
+    #[test]
+    fn test_image() {
+        let (depth, ssao) = scene();
+        let image = ssao.occlusion(&depth, 24, 24, &DepthState::new(255.0)).to_image();
+        assert_eq!(image.get_bytespp(), tga_image::GRAYSCALE as i32);
+        assert!(image.get(3, 10).raw()[0] < 255);
+        assert_eq!(image.get(12, 10).raw()[0], 255);
+    }
+    #[test]
+    fn test_pass() {
+        let (mut depth, mut ssao) = scene();
+        ssao.blur = 2;
+        depth[0] = std::f32::MIN;
+        let ao = ssao.occlusion(&depth, 24, 24, &DepthState::new(255.0));
+        assert_eq!(ao.get(0, 0)[0], 1.0);
+
+        let mut color = Attachment::new(24, 24, Format::Rgba32F);
+        color.clear([0.5, 1.0, 2.0, 1.0]);
+        let out = PostChain::new().then(ssao).run(&color, &depth, &DepthState::new(255.0));
+        let v = ao.get(3, 10)[0];
+        assert_eq!(out.get(3, 10), [0.5 * v, v, 2.0 * v, 1.0]);
+    }
+    #[test]
+    fn test_deferred_ambient() {
+        let mut gbuffer = GBuffer::new(2, 1);
+        for x in 0..2 {
+            let fb = gbuffer.framebuffer_mut();
+            fb.color_mut(GBuffer::ALBEDO).set(x, 0, [1.0; 4]);
+            fb.color_mut(GBuffer::NORMAL).set(x, 0, [0.0, 0.0, 1.0, 0.0]);
+            fb.color_mut(GBuffer::POSITION).set(x, 0, [0.0, 0.0, 0.0, 1.0]);
+        }
+        let mut ao = Attachment::new(2, 1, Format::R32F);
+        ao.set(0, 0, [0.5; 4]);
+        ao.set(1, 0, [1.0; 4]);
+        let (mut c0, mut c1) = ([0f32; 4], [0f32; 4]);
+        let lighting = DeferredLighting::new(&gbuffer, &[], Vec3f::new(0.0, 0.0, 1.0)).with_occlusion(&ao);
+        lighting.fragment(0, 0, &mut c0);
+        lighting.fragment(1, 0, &mut c1);
+        assert_eq!(c1[0], 0.08);
+        assert_eq!(c0[0], 0.04);
+    }
+}
+
+#[cfg(test)]
+mod test_bake {
+    use super::super::gl::*;
+    use super::common;
+
+    #[test]
+    fn test_bake_occlusion() {
+        let mut model = common::head();
+        let mut baker = OcclusionBaker::new();
+        baker.directions = 16;
+        baker.size = 64;
+        baker.shadow_size = 256;
+        let map = baker.bake(&model);
+        assert_eq!((map.get_width(), map.get_height(), map.get_bytespp()), (64, 64, tga_image::GRAYSCALE as i32));
+        let texels: Vec<u8> = (0..64 * 64).map(|i| map.get(i % 64, i / 64).raw()[0]).collect();
+        // the top of the head is open, the inside of the mouth is not
+        assert!(texels.iter().filter(|&&v| v > 240).count() > 64 * 64 / 2);
+        assert!(texels.iter().any(|&v| v < 64));
+
+        let uv = model.uv(0, 0);
+        assert_eq!(model.occlusion(uv), 1.0);
+        let expected = map.get((uv[0] * 64.0) as i32, (uv[1] * 64.0) as i32).raw()[0] as f32 / 255.0;
+        model.attach_texture(TextureSlot::Occlusion, map);
+        assert_eq!(model.occlusion(uv), expected);
+    }
+}
+
+#[cfg(test)]
+mod test_lights {
+    use super::super::gl::*;
+    use super::common;
+
+    #[test]
+    fn test_falloff() {
+        let (l, c) = Light::directional(Vec3f::new(0, 2, 0), Vec3f::new(1.0, 0.5, 0.0)).with_intensity(2.0).incident(Vec3f::new(5, 5, 5));
+        assert_eq!((l.x, l.y, l.z), (0.0, 1.0, 0.0));
+        assert_eq!((c.x, c.y, c.z), (2.0, 1.0, 0.0));
+
+        let point = Light::point(Vec3f::zero(), Vec3f::new(1, 1, 1), 4.0).with_intensity(2.0);
+        let (l, c) = point.incident(Vec3f::new(2, 0, 0));
+        assert_eq!((l.x, c.x), (-1.0, 0.5));
+        assert_eq!(point.incident(Vec3f::new(5, 0, 0)).1.x, 0.0);
+
+        let spot = Light::spot(Vec3f::zero(), Vec3f::new(0, 0, -1), Vec3f::new(1, 1, 1), 100.0, 0.2, 0.4);
+        let at = |angle: f32| spot.incident(Vec3f::new(angle.sin(), 0.0, -angle.cos())).1.x;
+        let full = at(0.0);
+        assert!((full - 0.99 * 0.99).abs() < 1.0e-4);
+        assert_eq!(at(0.15), full);
+        assert!(at(0.3) > 0.0 && at(0.3) < full);
+        assert!(at(0.35) < at(0.25));
+        assert_eq!(at(0.5), 0.0);
+        assert_eq!(spot.incident(Vec3f::new(0, 0, 1)).1.x, 0.0);
+    }
+
+    fn render(lights: &[Light], specular: f32) -> Attachment {
+        let (camera, model) = (common::camera(), common::textured_head());
+        let mut shader = BlinnPhongShader::new(&model, lights, common::eye());
+        shader.specular = specular;
+        let mut pipeline = Pipeline::new(120, 120, 255.0);
+        let mut framebuffer = Framebuffer::new(120, 120).with_color(Format::Rgba32F);
+        pipeline.draw(&camera, &model, &shader, &mut framebuffer);
+        framebuffer.color(0).clone()
+    }
+
+    #[test]
+    fn test_blinn_phong() {
+        let key = [Light::directional(Vec3f::new(1, 1, 1), Vec3f::new(1, 1, 1))];
+        let both = [key[0], Light::spot(Vec3f::new(0, 0, 2), Vec3f::new(0, 0, -1), Vec3f::new(1, 0, 0), 3.0, 0.2, 0.3)];
+        let lit = render(&key, 0.6);
+        let matte = render(&key, 0.0);
+        let red = render(&both, 0.6);
+        let (mut shiny, mut redder) = (0, 0);
+        for y in 0..120 {
+            for x in 0..120 {
+                let (a, m, r) = (lit.get(x, y), matte.get(x, y), red.get(x, y));
+                assert!(m[0] <= a[0] && m[1] <= a[1] && m[2] <= a[2]);
+                assert!(r[0] == a[0] && r[1] == a[1] && r[2] >= a[2]);
+                if a[1] > m[1] {
+                    shiny += 1;
+                }
+                if r[2] > a[2] {
+                    redder += 1;
+                }
+            }
+        }
+        assert!(shiny > 0);
+        // only the middle of the face is in the cone
+        assert!(redder > 0 && redder < 60 * 60);
+        assert!(red.get(60, 60)[2] > lit.get(60, 60)[2]);
+        assert_eq!(red.get(20, 100), lit.get(20, 100));
+    }
+}
+
+#[cfg(test)]
+mod test_pbr {
+    use super::super::gl::*;
+
+    /// Red, green, blue reflected towards `v` by a surface facing +z lit
+    /// along `l` by a white light.
+    fn reflected(model: &Model, material: PbrMaterial, l: Vec3f, v: Vec3f) -> [f32; 3] {
+        let lights = [Light::directional(l, Vec3f::new(1, 1, 1))];
+        let mut shader = PbrShader::new(model, &lights, v * 10.0, material);
+        shader.ambient = 0.0;
+        let c = shader.lit(&Surface { uv: Vec2f::new(0.5, 0.5), pos: Vec3f::zero(), normal: Vec3f::new(0, 0, 1), tangent: Vec4f::zero() });
+        [c.r, c.g, c.b]
+    }
+
+    #[test]
+    fn test_cook_torrance() {
+        let model = Model::open("obj/cube.obj");
+        let up = Vec3f::new(0, 0, 1);
+
+        // a white matte dielectric seen head on: what Fresnel leaves to the
+        // diffuse term plus a faint highlight
+        let white = reflected(&model, PbrMaterial::new().with_metallic(0.0), up, up);
+        assert!((white[0] - 0.97).abs() < 0.01);
+        assert!(white[0] == white[1] && white[1] == white[2]);
+
+        // metals have no diffuse term and tint the reflection
+        let red = PbrMaterial::new().with_base_color(ColorF::new(1.0, 0.0, 0.0)).with_roughness(0.3);
+        let c = reflected(&model, red, up, up);
+        assert!(c[0] > 0.0);
+        assert_eq!((c[1], c[2]), (0.0, 0.0));
+
+        // smoother surfaces concentrate the highlight around the mirror
+        // direction
+        let (l, mirror, off) = (Vec3f::new(1, 0, 1), Vec3f::new(-1, 0, 1).normalize(), Vec3f::new(1, 0, 2).normalize());
+        let smooth = PbrMaterial::new().with_roughness(0.2);
+        let rough = PbrMaterial::new().with_roughness(0.7);
+        assert!(reflected(&model, smooth, l, mirror)[0] > reflected(&model, rough, l, mirror)[0]);
+        assert!(reflected(&model, smooth, l, off)[0] < reflected(&model, rough, l, off)[0]);
+
+        // nothing reaches the back of the surface
+        assert_eq!(reflected(&model, PbrMaterial::new(), Vec3f::new(0, 0, -1), up), [0.0; 3]);
+    }
+
+    #[test]
+    fn test_metal_rough_map() {
+        let mut model = Model::open("obj/cube.obj");
+        let constants = PbrMaterial::new().with_metallic(0.2).with_roughness(0.4);
+        let expected = reflected(&model, constants, Vec3f::new(1, 0, 1), Vec3f::new(0, 1, 1));
+
+        // a single texel: blue holds the metalness, green the roughness
+        let mut map = TGAImage::with_info(1, 1, tga_image::RGB);
+        map.set(0, 0, Color::with_color(RGBColor(0, 204, 102)));
+        model.attach_texture(TextureSlot::MetalRough, map);
+        assert!(model.has_metal_rough_map());
+        let (metal, rough) = model.metal_rough(Vec2f::new(0.5, 0.5)).unwrap();
+        assert_eq!((metal, rough), (0.4, 0.8));
+        // the factors scale the map
+        let scaled = PbrMaterial::new().with_metallic(0.5).with_roughness(0.5);
+        let c = reflected(&model, scaled, Vec3f::new(1, 0, 1), Vec3f::new(0, 1, 1));
+        for i in 0..3 {
+            assert!((c[i] - expected[i]).abs() < 1.0e-5);
+        }
+    }
+}
+
+#[cfg(test)]
+mod test_tangents {
+    use super::super::gl::*;
+    use super::common;
+
+    #[test]
+    fn test_generate() {
+        let model = common::head();
+        for iface in 0..model.nfaces() as i32 {
+            for j in 0..3 {
+                let (t, n) = (model.tangent(iface, j), model.face_normal(iface, j));
+                assert!((t.proj3().norm() - 1.0).abs() < 1.0e-4);
+                assert!((t.proj3() * n).abs() < 1.0e-4);
+                assert!(t[3] == 1.0 || t[3] == -1.0);
+                assert!((model.bitangent(iface, j) * t.proj3()).abs() < 1.0e-4);
+            }
+        }
+        // the tangent follows u across the first face, up to the curvature
+        let uv: Vec<Vec2f> = (0..3).map(|j| model.uv(0, j)).collect();
+        let p: Vec<Vec3f> = (0..3).map(|j| model.face_vert(0, j)).collect();
+        let du = if (uv[1].x - uv[0].x).abs() > (uv[2].x - uv[0].x).abs() { (p[1] - p[0]) * (uv[1].x - uv[0].x) } else { (p[2] - p[0]) * (uv[2].x - uv[0].x) };
+        assert!(model.tangent(0, 0).proj3() * du > 0.0);
+
+        // nothing to derive them from without texture coordinates
+        let cube = Model::open("obj/cube.obj");
+        assert_eq!(cube.tangent(1, 0).proj3().norm(), 0.0);
+    }
+
+    #[test]
+    fn test_tangent_normal_map() {
+        let model = common::textured_head();
+        assert!(model.has_tangent_normal_map());
+        // both maps of the head describe the same surface
+        let mut sum = 0.0;
+        for iface in 0..model.nfaces() as i32 {
+            let v: Vec<Surface> = (0..3).map(|j| Surface::vertex(&Camera::new(255.0), &model, iface, j).1).collect();
+            let s = Surface::interpolate(&[v[0].clone(), v[1].clone(), v[2].clone()], Vec3f::new(1.0 / 3.0, 1.0 / 3.0, 1.0 / 3.0));
+            sum += s.shading_normal(&model) * model.normal(s.uv).normalize();
+        }
+        assert!(sum / model.nfaces() as f32 > 0.99);
+    }
+}
+
+#[cfg(test)]
+mod test_textures {
+    use super::super::gl::*;
+    use super::common;
+
+    fn texel(color: Color) -> TGAImage {
+        let mut image = TGAImage::with_info(1, 1, if color.nbytes() == 1 { tga_image::GRAYSCALE } else { tga_image::RGB });
+        image.set(0, 0, color);
+        image
+    }
+
+    #[test]
+    fn test_slots() {
+        for slot in TextureSlot::standard() {
+            assert_eq!(TextureSlot::from_name(slot.name()), slot);
+            let suffix = slot.suffix();
+            assert_eq!(TextureSlot::from_name(&suffix[1..suffix.len() - 4]), slot);
+        }
+        assert_eq!(TextureSlot::Normal.suffix(), "_nm.tga");
+        assert_eq!(TextureSlot::from_name("emissive"), TextureSlot::Glow);
+        assert_eq!(TextureSlot::from_name("mask"), TextureSlot::Custom("mask".to_string()));
+        assert_eq!(TextureSlot::from_name("mask").suffix(), "_mask.tga");
+
+        let diablo = Model::open_with_texture("obj/diablo3_pose.obj");
+        assert!(diablo.has_texture(&TextureSlot::Glow) && diablo.has_tangent_normal_map());
+        let eye = Model::open_with_texture("obj/african_head_eye_outer.obj");
+        assert!(eye.has_texture(&TextureSlot::Gloss));
+        assert!(!eye.has_texture(&TextureSlot::Glow));
+        assert_eq!(eye.glow(Vec2f::new(0.5, 0.5)).raw()[..3], [0, 0, 0]);
+    }
+
+    #[test]
+    fn test_attach() {
+        let mut model = Model::open("obj/cube.obj");
+        let uv = Vec2f::new(0.5, 0.5);
+        let mask = TextureSlot::from_name("mask");
+        assert!(model.sample(&mask, uv).is_none());
+        assert!(!model.load_texture("obj/cube.obj", mask.clone()));
+        model.attach_texture(mask.clone(), texel(Color::with_color(RGBColor(10, 20, 30))));
+        assert_eq!(model.texture_slots(), vec![&mask]);
+        assert_eq!(model.sample(&mask, uv).unwrap().raw()[..3], [30, 20, 10]);
+        assert!(model.detach_texture(&mask).is_some());
+        assert!(!model.has_texture(&mask));
+    }
+
+    #[test]
+    fn test_blinn_phong_maps() {
+        let mut model = Model::open("obj/cube.obj");
+        model.attach_texture(TextureSlot::Diffuse, texel(Color::with_color(RGBColor(128, 128, 128))));
+        model.attach_texture(TextureSlot::Specular, texel(Color::with_color(GRAY(10))));
+        let lights = [Light::directional(Vec3f::new(0, 0, 1), Vec3f::new(1, 1, 1))];
+        let s = Surface { uv: Vec2f::new(0.5, 0.5), pos: Vec3f::zero(), normal: Vec3f::new(0, 0, 1), tangent: Vec4f::zero() };
+        let lit = |model: &Model| BlinnPhongShader::new(model, &lights, Vec3f::new(0, 0, 5)).lit(&s);
+        let shiny = lit(&model);
+
+        // a gloss of zero leaves no highlight
+        model.attach_texture(TextureSlot::Gloss, texel(Color::with_color(GRAY(0))));
+        let matte = lit(&model);
+        assert!((shiny.g - matte.g - 0.6).abs() < 1.0e-4);
+
+        // the glow adds up to the rest
+        model.attach_texture(TextureSlot::Glow, texel(Color::with_color(RGBColor(255, 0, 0))));
+        let glowing = lit(&model);
+        assert_eq!((glowing.r, glowing.g), (matte.r + 1.0, matte.g));
+    }
+
+    #[test]
+    fn test_texture_shader() {
+        let mut model = common::textured_head();
+        model.attach_texture(TextureSlot::from_name("mask"), texel(Color::with_color(RGBColor(0, 255, 0))));
+        let camera = common::look(Vec3f::new(0, 0, 3), 0, 0, 32);
+        let draw = |name: &str| {
+            let (mut pipeline, mut image, mut zbuffer) = common::targets(32, 32);
+            pipeline.draw(&camera, &model, &TextureShader::new(&model, name), Target::new(&mut image, &mut zbuffer));
+            image
+        };
+        assert_eq!(draw("mask").get(16, 16).raw()[..3], [0, 255, 0]);
+        // grayscale textures come out gray
+        let spec = draw("spec").get(16, 16).raw();
+        assert!(spec[0] == spec[1] && spec[1] == spec[2]);
+        assert_eq!(draw("glow").get(16, 16).raw()[..3], [0, 0, 0]);
+    }
+}